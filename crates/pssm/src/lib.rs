@@ -1,13 +1,21 @@
 pub mod prelude {
     pub use pssm_core::{
+        SendStateMachine,
+        SharedStateMachine,
+        Snapshot,
+        SnapshotError,
         StateMachine,
+        StateView,
         Truth,
+        TruthId,
         TransitionCallError,
         into_transition_with,
         into_transition_mut_with,
         into_transition_once_with,
         transition::*,
     };
+    #[cfg(feature = "serde")]
+    pub use pssm_core::{RegistryError, SerializableStateMachine, TruthRegistry};
     pub use pssm_dictionary::{Dictionary, TransitionDictionary};
     pub use pssm_macro::Truth;
 }
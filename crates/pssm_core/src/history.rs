@@ -0,0 +1,64 @@
+use std::collections::VecDeque;
+
+use crate::state::Captured;
+
+/// A transition that was run, recorded as the truths it touched before and after it ran.
+pub(crate) struct Step {
+    pub(crate) before: Captured,
+    pub(crate) after: Captured
+}
+
+/// The transitions run by a state machine, which can be undone and redone.
+///
+/// At most `depth` steps are kept to be undone, older steps are forgotten.
+/// Recording a new step forgets every step that could be redone.
+pub(crate) struct History {
+    depth: usize,
+    undo: VecDeque<Step>,
+    redo: Vec<Step>
+}
+
+impl History {
+    pub(crate) fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            undo: VecDeque::new(),
+            redo: Vec::new()
+        }
+    }
+
+    pub(crate) fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
+    }
+
+    pub(crate) fn record(&mut self, step: Step) {
+        self.redo.clear();
+        self.undo.push_back(step);
+        self.set_depth(self.depth);
+    }
+
+    pub(crate) fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub(crate) fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Moves the last step to the redo stack and returns it.
+    pub(crate) fn undo(&mut self) -> Option<&Step> {
+        let step = self.undo.pop_back()?;
+        self.redo.push(step);
+        self.redo.last()
+    }
+
+    /// Moves the last undone step back to the undo stack and returns it.
+    pub(crate) fn redo(&mut self) -> Option<&Step> {
+        let step = self.redo.pop()?;
+        self.undo.push_back(step);
+        self.undo.back()
+    }
+}
@@ -1,23 +1,70 @@
+mod history;
+mod observer;
+#[cfg(feature = "serde")]
+mod registry;
+mod sendstatemachine;
+mod sharedstatemachine;
+mod snapshot;
+mod state;
 mod statemachine;
 
 pub mod transition;
 
-use std::{any::{Any, TypeId}, collections::HashMap};
+use std::{any::{Any, TypeId}, error::Error, fmt::Debug, hash::Hash};
 
+#[cfg(feature = "serde")]
+pub use registry::{RegistryError, SerializableStateMachine, TruthRegistry};
+pub use sendstatemachine::SendStateMachine;
+pub use sharedstatemachine::SharedStateMachine;
+pub use snapshot::{Snapshot, SnapshotError};
+pub use state::StateView;
 pub use statemachine::StateMachine;
 
+use crate::state::State;
 use crate::transition::{InvalidTransitionError, TransitionError};
 
-type State = HashMap<Id, Box<dyn Any>>;
-type Id = TypeId;
+type Id = TruthId;
 
 /// An Error that can occur when calling an object that can be converted into a transition.
 /// 
 /// This error can occur when calling an object that can be converted into a transition, but the object is not a valid transition,
-/// or when the transition is run on a state that does not contain all of the required truths for the transition.
+/// when the transition is run on a state that does not contain all of the required truths for the transition,
+/// when a fallible transition function returns an error, when a fallible transition could not be rolled back if it failed,
+/// or when a transition can not be recorded in the history of the state machine.
+/// If a fallible transition function returns an error, the error returned by the function is passed on as is,
+/// boxed as `Box<dyn Error + Send + Sync>`. The error type of a fallible function has to convert into it, see `IntoTransition`.
+/// 
+/// The underlying error is available through `Error::source`.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use std::error::Error;
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth,Debug)]
+/// struct A();
+/// 
+/// fn consume_a(a: A) {
+///     println!("{:?}", a);
+/// }
+/// 
+/// fn run() -> Result<(), Box<dyn Error>> {
+///     let mut state_machine = StateMachine::new();
+///     state_machine.run(consume_a)?;
+///     Ok(())
+/// }
+/// 
+/// let error = run().unwrap_err();
+/// assert_eq!(error.to_string(), "transition could not be run");
+/// assert_eq!(error.source().unwrap().to_string(), format!("missing truth `{}`", A::name()));
+/// ```
 pub enum TransitionCallError {
     InvalidTransition(InvalidTransitionError),
-    TransitionError(TransitionError)
+    TransitionError(TransitionError),
+    Failed(Box<dyn Error + Send + Sync>),
+    NotRestorable(SnapshotError),
+    NotRecordable(SnapshotError)
 }
 
 impl From<InvalidTransitionError> for TransitionCallError {
@@ -28,15 +75,45 @@ impl From<InvalidTransitionError> for TransitionCallError {
 
 impl From<TransitionError> for TransitionCallError {
     fn from(value: TransitionError) -> Self {
-        TransitionCallError::TransitionError(value)
+        match value {
+            TransitionError::Failed(e) => TransitionCallError::Failed(e),
+            e => TransitionCallError::TransitionError(e)
+        }
     }
 }
 
-impl std::fmt::Debug for TransitionCallError {
+impl Debug for TransitionCallError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             TransitionCallError::InvalidTransition(e) => e.fmt(f),
-            TransitionCallError::TransitionError(e) => e.fmt(f)
+            TransitionCallError::TransitionError(e) => e.fmt(f),
+            TransitionCallError::Failed(e) => e.fmt(f),
+            TransitionCallError::NotRestorable(e) => e.fmt(f),
+            TransitionCallError::NotRecordable(e) => e.fmt(f)
+        }
+    }
+}
+
+impl std::fmt::Display for TransitionCallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransitionCallError::InvalidTransition(_) => f.write_str("invalid transition"),
+            TransitionCallError::TransitionError(_) => f.write_str("transition could not be run"),
+            TransitionCallError::Failed(_) => f.write_str("transition function failed"),
+            TransitionCallError::NotRestorable(_) => f.write_str("transition can not be rolled back if it fails"),
+            TransitionCallError::NotRecordable(_) => f.write_str("transition can not be recorded in the history")
+        }
+    }
+}
+
+impl Error for TransitionCallError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TransitionCallError::InvalidTransition(e) => Some(e),
+            TransitionCallError::TransitionError(e) => Some(e),
+            TransitionCallError::Failed(e) => Some(e.as_ref()),
+            TransitionCallError::NotRestorable(e) => Some(e),
+            TransitionCallError::NotRecordable(e) => Some(e)
         }
     }
 }
@@ -54,7 +131,141 @@ impl std::fmt::Debug for TransitionCallError {
 /// struct A();
 /// 
 /// assert_eq!(A::id(), std::any::TypeId::of::<A>());
+/// assert_eq!(A::name(), std::any::type_name::<A>());
 /// ```
 pub trait Truth {
-    fn id() -> Id;
+    fn id() -> TypeId;
+
+    /// Returns the name of this truth, used in error messages.
+    /// 
+    /// Defaults to the full path of the type.
+    fn name() -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Returns a copy of this truth, if it can be cloned.
+    /// 
+    /// Only truths that can be cloned are captured by `StateMachine::snapshot`.
+    /// Defaults to `None`, deriving `Truth` with `#[truth(clone)]` implements it using `Clone`.
+    fn try_clone(&self) -> Option<Self>
+    where 
+        Self: Sized
+    {
+        None
+    }
+
+    /// Returns the tags this truth is marked with, see `Tagged`.
+    /// 
+    /// Defaults to no tags, deriving `Truth` with `#[truth(tag = "...")]` adds a tag.
+    fn tags() -> &'static [&'static str] {
+        &[]
+    }
+}
+
+fn clone_truth<T: Truth + 'static>(truth: &dyn Any) -> Option<Box<dyn Any>> {
+    truth.downcast_ref::<T>()
+        .and_then(T::try_clone)
+        .map(|truth| Box::new(truth) as Box<dyn Any>)
+}
+
+/// Identifies a type of truth.
+/// 
+/// Two ids are equal if they identify the same type. The name of the truth is only carried along
+/// to make errors and other output readable. Ids are ordered by their `TypeId`, so the order is
+/// consistent within a build, but not between builds.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth)]
+/// struct A();
+/// 
+/// let id = TruthId::of::<A>();
+/// 
+/// assert_eq!(id.type_id(), std::any::TypeId::of::<A>());
+/// assert_eq!(id.to_string(), std::any::type_name::<A>());
+/// ```
+#[derive(Clone, Copy)]
+pub struct TruthId {
+    type_id: TypeId,
+    name: &'static str,
+    tags: &'static [&'static str],
+    clone: fn(&dyn Any) -> Option<Box<dyn Any>>
+}
+
+impl TruthId {
+    /// Returns the id of the given truth.
+    pub fn of<T: Truth + 'static>() -> Self {
+        Self {
+            type_id: T::id(),
+            name: T::name(),
+            tags: T::tags(),
+            clone: clone_truth::<T>
+        }
+    }
+
+    /// Returns the id of the truths of type `T` stored under keys of type `K`, see `Keyed`.
+    pub fn of_keyed<K: Ord + Clone + 'static, T: Truth + 'static>() -> Self {
+        Self::of::<transition::keyed::KeyedTruths<K,T>>()
+    }
+
+    /// Returns the `TypeId` of the truth.
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    /// Returns the name of the truth.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the tags the truth is marked with.
+    pub fn tags(&self) -> &'static [&'static str] {
+        self.tags
+    }
+
+    /// Clones a truth of the type identified by this id.
+    pub(crate) fn clone_truth(&self, truth: &dyn Any) -> Option<Box<dyn Any>> {
+        (self.clone)(truth)
+    }
+}
+
+impl PartialEq for TruthId {
+    fn eq(&self, other: &Self) -> bool {
+        self.type_id == other.type_id
+    }
+}
+
+impl Eq for TruthId {}
+
+impl PartialOrd for TruthId {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TruthId {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.type_id.cmp(&other.type_id)
+    }
+}
+
+impl Hash for TruthId {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.type_id.hash(state);
+    }
+}
+
+impl Debug for TruthId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl std::fmt::Display for TruthId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,65 @@
+use std::{any::Any, collections::HashMap};
+
+use crate::{Id, TransitionCallError};
+use crate::transition::Signature;
+
+type SetFn = dyn FnMut(&dyn Any);
+type UnsetFn = dyn FnMut();
+type BeforeFn = dyn FnMut(&Signature);
+type AfterFn = dyn FnMut(&Signature, Result<(), &TransitionCallError>);
+
+/// The callbacks a state machine notifies when truths enter or leave its state, and around every transition it runs.
+#[derive(Default)]
+pub(crate) struct Observers {
+    on_set: HashMap<Id, Vec<Box<SetFn>>>,
+    on_unset: HashMap<Id, Vec<Box<UnsetFn>>>,
+    before: Vec<Box<BeforeFn>>,
+    after: Vec<Box<AfterFn>>
+}
+
+impl Observers {
+    pub(crate) fn add_set(&mut self, id: Id, callback: Box<SetFn>) {
+        self.on_set.entry(id).or_default().push(callback);
+    }
+
+    pub(crate) fn add_unset(&mut self, id: Id, callback: Box<UnsetFn>) {
+        self.on_unset.entry(id).or_default().push(callback);
+    }
+
+    pub(crate) fn add_before(&mut self, callback: Box<BeforeFn>) {
+        self.before.push(callback);
+    }
+
+    pub(crate) fn add_after(&mut self, callback: Box<AfterFn>) {
+        self.after.push(callback);
+    }
+
+    /// Checks if any truth is observed. Otherwise, the truths a transition changed do not have to be collected.
+    pub(crate) fn observes_truths(&self) -> bool {
+        !self.on_set.is_empty() || !self.on_unset.is_empty()
+    }
+
+    pub(crate) fn set(&mut self, id: &Id, truth: &dyn Any) {
+        for callback in self.on_set.get_mut(id).into_iter().flatten() {
+            callback(truth);
+        }
+    }
+
+    pub(crate) fn unset(&mut self, id: &Id) {
+        for callback in self.on_unset.get_mut(id).into_iter().flatten() {
+            callback();
+        }
+    }
+
+    pub(crate) fn before(&mut self, signature: &Signature) {
+        for callback in &mut self.before {
+            callback(signature);
+        }
+    }
+
+    pub(crate) fn after(&mut self, signature: &Signature, result: Result<(), &TransitionCallError>) {
+        for callback in &mut self.after {
+            callback(signature, result);
+        }
+    }
+}
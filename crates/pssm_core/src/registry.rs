@@ -0,0 +1,249 @@
+use std::{any::Any, collections::HashMap, error::Error, fmt::{Debug, Display}};
+
+use serde::{de::{DeserializeOwned, DeserializeSeed, Error as _, MapAccess, Visitor}, ser::{Error as _, SerializeMap}, Deserializer, Serialize, Serializer};
+
+use crate::{transition::keyed::KeyedTruths, Id, State, StateMachine, Truth};
+
+type SerializeFn = fn(&dyn Any) -> &dyn erased_serde::Serialize;
+type DeserializeFn = fn(&mut dyn erased_serde::Deserializer) -> Result<Box<dyn Any>, erased_serde::Error>;
+
+struct Entry {
+    id: Id,
+    serialize: SerializeFn,
+    deserialize: DeserializeFn
+}
+
+fn serialize_truth<T: Truth + Serialize + 'static>(truth: &dyn Any) -> &dyn erased_serde::Serialize {
+    truth.downcast_ref::<T>().expect("Invalid type stored for a truth in the state")
+}
+
+fn deserialize_truth<T: Truth + DeserializeOwned + 'static>(deserializer: &mut dyn erased_serde::Deserializer) -> Result<Box<dyn Any>, erased_serde::Error> {
+    erased_serde::deserialize::<T>(deserializer).map(|truth| Box::new(truth) as Box<dyn Any>)
+}
+
+/// A registry of the truths that can be serialized.
+///
+/// Every truth is registered under a stable name, which is used to identify it in the serialized state.
+/// Unlike the `TypeId` of a truth, the name does not change between builds, so a state serialized by one
+/// build of a program can be deserialized by another.
+///
+/// A state machine is serialized as a map from the names of its truths to their values.
+/// Serializing a state machine that contains a truth which is not registered fails,
+/// as does deserializing a name which is not registered. Keyed truths are registered with `register_keyed`.
+///
+/// This registry is only available with the `serde` feature.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Truth,Serialize,Deserialize)]
+/// struct User(String);
+///
+/// #[derive(Truth,Serialize,Deserialize)]
+/// struct LoggedIn();
+///
+/// let mut registry = TruthRegistry::new();
+/// registry.register::<User>("user").unwrap();
+/// registry.register::<LoggedIn>("logged_in").unwrap();
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(User("alice".to_string()));
+/// state_machine.set_truth(LoggedIn());
+///
+/// let json = serde_json::to_string(&state_machine.serializable(&registry)).unwrap();
+/// assert_eq!(json, r#"{"logged_in":[],"user":"alice"}"#);
+///
+/// let mut deserializer = serde_json::Deserializer::from_str(&json);
+/// let mut restored = registry.deserialize_state_machine(&mut deserializer).unwrap();
+///
+/// assert!(restored.has_truth::<LoggedIn>());
+/// assert_eq!(restored.unset_truth::<User>().unwrap().0, "alice");
+/// ```
+#[derive(Default)]
+pub struct TruthRegistry {
+    entries: HashMap<&'static str, Entry>,
+    names: HashMap<Id, &'static str>
+}
+
+impl TruthRegistry {
+    /// Creates a new empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a truth under the given name.
+    ///
+    /// Returns an error if the name is already used by another truth, or if the truth is already registered.
+    pub fn register<T>(&mut self, name: &'static str) -> Result<(), RegistryError>
+    where
+        T: Truth + Serialize + DeserializeOwned + 'static
+    {
+        let id = Id::of::<T>();
+
+        if self.entries.contains_key(name) {
+            return Err(RegistryError::NameTaken(name));
+        }
+        if self.names.contains_key(&id) {
+            return Err(RegistryError::TruthRegistered(id));
+        }
+
+        self.entries.insert(name, Entry {
+            id,
+            serialize: serialize_truth::<T>,
+            deserialize: deserialize_truth::<T>
+        });
+        self.names.insert(id, name);
+        Ok(())
+    }
+
+    /// Registers the keyed truths of type `T` with keys of type `K` under the given name.
+    ///
+    /// All the truths of type `T` stored under keys of type `K` are serialized together, as a map from their keys to
+    /// their values. Returns an error if the name is already used by another truth, or if the keyed truths are
+    /// already registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Truth,Serialize,Deserialize)]
+    /// struct Order(u32);
+    ///
+    /// let mut registry = TruthRegistry::new();
+    /// registry.register_keyed::<u64, Order>("orders").unwrap();
+    ///
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth_keyed(7u64, Order(10));
+    /// state_machine.set_truth_keyed(3u64, Order(20));
+    ///
+    /// let json = serde_json::to_string(&state_machine.serializable(&registry)).unwrap();
+    /// assert_eq!(json, r#"{"orders":{"3":20,"7":10}}"#);
+    ///
+    /// let mut deserializer = serde_json::Deserializer::from_str(&json);
+    /// let mut restored = registry.deserialize_state_machine(&mut deserializer).unwrap();
+    ///
+    /// assert_eq!(restored.unset_truth_keyed::<u64, Order>(&3).unwrap().0, 20);
+    /// assert_eq!(restored.unset_truth_keyed::<u64, Order>(&7).unwrap().0, 10);
+    /// ```
+    pub fn register_keyed<K,T>(&mut self, name: &'static str) -> Result<(), RegistryError>
+    where
+        K: Ord + Clone + Serialize + DeserializeOwned + 'static,
+        T: Truth + Serialize + DeserializeOwned + 'static
+    {
+        self.register::<KeyedTruths<K,T>>(name)
+    }
+
+    /// Returns the name the given truth is registered under.
+    pub fn name_of(&self, id: &Id) -> Option<&'static str> {
+        self.names.get(id).copied()
+    }
+
+    /// Deserializes a state machine, using the truths in this registry.
+    pub fn deserialize_state_machine<'de, D: Deserializer<'de>>(&self, deserializer: D) -> Result<StateMachine, D::Error> {
+        DeserializeSeed::deserialize(self, deserializer)
+    }
+}
+
+impl Debug for TruthRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.entries.iter().map(|(name, entry)| (name, entry.id))).finish()
+    }
+}
+
+/// An Error that can occur when registering a truth in a `TruthRegistry`.
+pub enum RegistryError {
+    NameTaken(&'static str),
+    TruthRegistered(Id)
+}
+
+impl Debug for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::NameTaken(name) => write!(f, "name `{}` is already registered", name),
+            RegistryError::TruthRegistered(id) => write!(f, "truth `{}` is already registered", id)
+        }
+    }
+}
+
+impl Error for RegistryError {}
+
+/// A state machine that can be serialized, using the truths in a `TruthRegistry`.
+///
+/// Created by `StateMachine::serializable`.
+pub struct SerializableStateMachine<'s> {
+    pub(crate) state: &'s State,
+    pub(crate) registry: &'s TruthRegistry
+}
+
+impl Serialize for SerializableStateMachine<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut truths = Vec::new();
+        for (id, truth) in self.state.iter() {
+            let name = self.registry.name_of(id)
+                .ok_or_else(|| S::Error::custom(format!("truth `{}` is not registered", id)))?;
+            truths.push((name, (self.registry.entries[name].serialize)(truth)));
+        }
+        truths.sort_by_key(|(name, _)| *name);
+
+        let mut map = serializer.serialize_map(Some(truths.len()))?;
+        for (name, truth) in truths {
+            map.serialize_entry(name, truth)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for &TruthRegistry {
+    type Value = StateMachine;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<StateMachine, D::Error> {
+        deserializer.deserialize_map(StateVisitor(self))
+    }
+}
+
+struct StateVisitor<'r>(&'r TruthRegistry);
+
+impl<'de> Visitor<'de> for StateVisitor<'_> {
+    type Value = StateMachine;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a map of truths")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<StateMachine, A::Error> {
+        let mut truths = HashMap::new();
+
+        while let Some(name) = map.next_key::<String>()? {
+            let entry = self.0.entries.get(name.as_str())
+                .ok_or_else(|| A::Error::custom(format!("truth `{}` is not registered", name)))?;
+            let truth = map.next_value_seed(TruthSeed(entry))?;
+            truths.insert(entry.id, truth);
+        }
+
+        let mut state = State::default();
+        state.replace(truths);
+        Ok(StateMachine::from_state(state))
+    }
+}
+
+struct TruthSeed<'r>(&'r Entry);
+
+impl<'de> DeserializeSeed<'de> for TruthSeed<'_> {
+    type Value = Box<dyn Any>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Box<dyn Any>, D::Error> {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.0.deserialize)(&mut deserializer).map_err(D::Error::custom)
+    }
+}
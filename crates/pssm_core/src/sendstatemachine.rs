@@ -0,0 +1,251 @@
+use std::{any::Any, collections::HashMap, panic, thread};
+
+use crate::{Id, SnapshotError, State, TransitionCallError, Truth, TruthId};
+use crate::transition::{SendTransition, TransitionError};
+
+/// Truths that can be sent to another thread.
+pub(crate) type Truths = HashMap<Id, Box<dyn Any + Send>>;
+
+/// A state machine whose truths can be sent to other threads.
+///
+/// This state machine works like `StateMachine`, but it only stores truths that implement `Send`
+/// and only runs `SendTransition`s. In exchange, the state machine itself can be sent to another thread,
+/// and `run_parallel` can run several transitions at the same time.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct A(i32);
+///
+/// fn double(a: A) -> A {
+///     A(a.0 * 2)
+/// }
+///
+/// let transition = double.into_send_transition().unwrap();
+///
+/// let mut state_machine = SendStateMachine::new();
+/// state_machine.set_truth(A(2));
+///
+/// let mut state_machine = std::thread::spawn(move || {
+///     state_machine.run(&transition).unwrap();
+///     state_machine
+/// }).join().unwrap();
+///
+/// assert_eq!(state_machine.unset_truth::<A>().unwrap().0, 4);
+/// ```
+#[derive(Default)]
+pub struct SendStateMachine {
+    truths: Truths
+}
+
+impl SendStateMachine {
+    /// Creates a new state machine with an empty state.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks if a `SendTransition` can be run.
+    ///
+    /// This function will check if the required truths for the transition are in the state,
+    /// and if the truths it requires to be absent are not.
+    pub fn can_run(&self, transition: &SendTransition) -> bool {
+        transition.signature.check_with(|id| self.truths.contains_key(id)).is_ok()
+    }
+
+    /// Runs a `SendTransition`.
+    ///
+    /// This function will run the transition if all the required truths are in the state.
+    /// If the transition requires a truth that is not in the state, or a truth to be absent that is in the state,
+    /// this function will return an error.
+    ///
+    /// Like `StateMachine::run`, the transition is run atomically.
+    pub fn run(&mut self, transition: &SendTransition) -> Result<(),TransitionCallError> {
+        transition.signature.check_with(|id| self.truths.contains_key(id))?;
+        let truths = take(&mut self.truths, transition);
+        let (result, truths) = execute(transition, truths);
+        self.truths.extend(truths);
+        result
+    }
+
+    /// Runs several `SendTransition`s, running transitions that do not conflict at the same time.
+    ///
+    /// The transitions are split into batches. A transition is put into the batch after the last earlier transition
+    /// it conflicts with, two transitions conflict if they can take, change or insert the same truth, or if one of them
+    /// can insert a truth the other one forbids. Every batch takes the truths of its transitions out of the state,
+    /// runs each transition on its own truths in a scoped thread, and merges the results back into the state.
+    ///
+    /// Transitions that conflict run in the order they are given, so the state afterwards is the same
+    /// as if every transition was run with `run` one after another.
+    ///
+    /// Returns the result of every transition, in the order they are given. A transition that can not be run
+    /// or fails does not stop the other transitions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    ///
+    /// #[derive(Truth,Debug)]
+    /// struct A(i32);
+    ///
+    /// #[derive(Truth,Debug)]
+    /// struct B(i32);
+    ///
+    /// #[derive(Truth,Debug)]
+    /// struct Sum(i32);
+    ///
+    /// fn increment_a(a: &mut A) {
+    ///     a.0 += 1;
+    /// }
+    ///
+    /// fn increment_b(b: &mut B) {
+    ///     b.0 += 1;
+    /// }
+    ///
+    /// fn sum(a: &A, b: &B) -> Sum {
+    ///     Sum(a.0 + b.0)
+    /// }
+    ///
+    /// let transitions = [
+    ///     increment_a.into_send_transition().unwrap(),
+    ///     increment_b.into_send_transition().unwrap(),
+    ///     sum.into_send_transition().unwrap(),
+    /// ];
+    ///
+    /// let mut state_machine = SendStateMachine::new();
+    /// state_machine.set_truth(A(1));
+    /// state_machine.set_truth(B(2));
+    ///
+    /// let results = state_machine.run_parallel(&transitions);
+    ///
+    /// assert!(results.iter().all(Result::is_ok));
+    /// assert_eq!(state_machine.unset_truth::<Sum>().unwrap().0, 5);
+    /// ```
+    pub fn run_parallel(&mut self, transitions: &[SendTransition]) -> Vec<Result<(),TransitionCallError>> {
+        let mut results: Vec<Option<Result<(),TransitionCallError>>> = transitions.iter().map(|_| None).collect();
+
+        for batch in batches(transitions) {
+            let mut jobs = Vec::with_capacity(batch.len());
+            for index in batch {
+                let transition = &transitions[index];
+                match transition.signature.check_with(|id| self.truths.contains_key(id)) {
+                    Ok(()) => jobs.push((index, take(&mut self.truths, transition))),
+                    Err(e) => results[index] = Some(Err(e.into()))
+                }
+            }
+
+            let done: Vec<_> = if jobs.len() == 1 {
+                jobs.into_iter()
+                    .map(|(index, truths)| (index, execute(&transitions[index], truths)))
+                    .collect()
+            } else {
+                thread::scope(|scope| {
+                    let handles: Vec<_> = jobs.into_iter()
+                        .map(|(index, truths)| {
+                            let transition = &transitions[index];
+                            (index, scope.spawn(move || execute(transition, truths)))
+                        })
+                        .collect();
+
+                    handles.into_iter()
+                        .map(|(index, handle)| (index, handle.join().unwrap_or_else(|payload| panic::resume_unwind(payload))))
+                        .collect()
+                })
+            };
+
+            for (index, (result, truths)) in done {
+                self.truths.extend(truths);
+                results[index] = Some(result);
+            }
+        }
+
+        results.into_iter()
+            .map(|result| result.expect("Every transition is part of a batch"))
+            .collect()
+    }
+
+    /// Sets a truth in the state.
+    ///
+    /// This function will insert the truth into the state.
+    /// If a truth of the same type is already in the state, this function will replace it.
+    pub fn set_truth<T: Truth + Send + 'static>(&mut self, element: T) {
+        self.truths.insert(Id::of::<T>(), Box::new(element));
+    }
+
+    /// Checks if a truth is in the state.
+    pub fn has_truth<T: Truth + 'static>(&self) -> bool {
+        self.truths.contains_key(&Id::of::<T>())
+    }
+
+    /// Returns the ids of all truths in the state.
+    pub fn truths(&self) -> impl Iterator<Item = TruthId> + '_ {
+        self.truths.keys().copied()
+    }
+
+    /// Unsets a truth in the state.
+    ///
+    /// This function will remove the truth from the state and return it.
+    /// If the truth is not in the state, this function will return an error.
+    pub fn unset_truth<T: Truth + Send + 'static>(&mut self) -> Result<T, TransitionError> {
+        let id = Id::of::<T>();
+        self.truths.remove(&id)
+            .map(|truth| *truth.downcast::<T>().expect("Invalid type stored for a truth in the state"))
+            .ok_or(TransitionError::MissingTruth(id))
+    }
+}
+
+/// Takes the truths the transition can take or change out of the given truths.
+pub(crate) fn take(truths: &mut Truths, transition: &SendTransition) -> Truths {
+    transition.signature.requires.iter()
+        .chain(&transition.signature.optional)
+        .filter_map(|id| truths.remove_entry(id))
+        .collect()
+}
+
+/// Splits the transitions into batches of transitions that do not conflict with each other.
+///
+/// Every transition is put into the batch after the last batch containing an earlier transition it conflicts with.
+fn batches(transitions: &[SendTransition]) -> Vec<Vec<usize>> {
+    let mut levels: Vec<usize> = Vec::with_capacity(transitions.len());
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+
+    for (index, transition) in transitions.iter().enumerate() {
+        let level = (0..index)
+            .filter(|earlier| transitions[*earlier].conflicts_with(transition))
+            .map(|earlier| levels[earlier] + 1)
+            .max()
+            .unwrap_or(0);
+
+        levels.push(level);
+        match batches.get_mut(level) {
+            Some(batch) => batch.push(index),
+            None => batches.push(vec![index])
+        }
+    }
+
+    batches
+}
+
+/// Runs a transition as a transaction on the given truths, and returns its result together with the truths it left.
+pub(crate) fn execute(transition: &SendTransition, truths: Truths) -> (Result<(),TransitionCallError>, Truths) {
+    let mut state = State::default();
+    for (id, truth) in truths {
+        state.put_back(id, truth);
+    }
+
+    let result = transition.signature.check_restorable(&state)
+        .map_err(|ids| TransitionCallError::NotRestorable(SnapshotError::NotCloneable(ids)))
+        .and_then(|()| state.transaction(|state| transition.run(state)).map_err(TransitionCallError::from));
+    let truths = state.into_truths()
+        .into_iter()
+        .map(|(id, truth)| {
+            let truth = transition.send(&id, truth);
+            (id, truth)
+        })
+        .collect();
+
+    (result, truths)
+}
@@ -0,0 +1,245 @@
+use std::{collections::{HashMap, HashSet}, mem, sync::{Condvar, Mutex, MutexGuard, PoisonError}};
+
+use crate::{Id, TransitionCallError, Truth, TruthId};
+use crate::sendstatemachine::{execute, take, Truths};
+use crate::transition::{SendTransition, TransitionError};
+
+/// The state of a `SharedStateMachine`, together with the truths held by running transitions.
+#[derive(Default)]
+struct Inner {
+    truths: Truths,
+    held: HashSet<Id>,
+    forbidden: HashMap<Id, usize>
+}
+
+impl Inner {
+    /// Returns a truth the transition needs that is held by a running transition, if there is one.
+    fn blocked_by(&self, transition: &SendTransition) -> Option<Id> {
+        transition.touches()
+            .find(|id| self.held.contains(id) || self.forbidden.contains_key(id))
+            .or_else(|| transition.signature.forbids.iter().find(|id| self.held.contains(id)))
+            .copied()
+    }
+
+    fn hold(&mut self, transition: &SendTransition) {
+        self.held.extend(transition.touches());
+        for id in &transition.signature.forbids {
+            *self.forbidden.entry(*id).or_default() += 1;
+        }
+    }
+
+    fn release(&mut self, transition: &SendTransition) {
+        for id in transition.touches() {
+            self.held.remove(id);
+        }
+        for id in &transition.signature.forbids {
+            if let Some(count) = self.forbidden.get_mut(id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.forbidden.remove(id);
+                }
+            }
+        }
+    }
+}
+
+/// The truths a running transition holds, released when it is dropped, even if the transition panics.
+struct Hold<'m,'t,'a> {
+    machine: &'m SharedStateMachine,
+    transition: &'t SendTransition<'a>,
+    truths: Truths
+}
+
+impl<'m,'t,'a> Drop for Hold<'m,'t,'a> {
+    fn drop(&mut self) {
+        let mut inner = self.machine.lock();
+        inner.truths.extend(mem::take(&mut self.truths));
+        inner.release(self.transition);
+        drop(inner);
+        self.machine.released.notify_all();
+    }
+}
+
+/// A state machine that can be shared between threads.
+///
+/// This state machine stores truths that implement `Send` and `Sync`, and runs `SendTransition`s through a shared reference,
+/// so it can be put into an `Arc` or borrowed by scoped threads. Truths set or unset directly have to implement both.
+/// The truths of a `SendTransition` only have to implement `Send`, like in a `SendStateMachine`.
+/// 
+/// Every running transition holds the truths it can take, change or insert, and the truths it forbids. Transitions that
+/// do not need any held truth run at the same time, `run` blocks until the truths a transition needs are released,
+/// and `try_run` fails with `TransitionError::TruthHeld` instead.
+///
+/// A transition only holds its truths while it runs, so a sequence of transitions is not atomic.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct Counter(i32);
+///
+/// fn increment(counter: &mut Counter) {
+///     counter.0 += 1;
+/// }
+///
+/// let transition = increment.into_send_transition().unwrap();
+///
+/// let state_machine = SharedStateMachine::new();
+/// state_machine.set_truth(Counter(0));
+///
+/// std::thread::scope(|scope| {
+///     for _ in 0..4 {
+///         scope.spawn(|| {
+///             for _ in 0..100 {
+///                 state_machine.run(&transition).unwrap();
+///             }
+///         });
+///     }
+/// });
+///
+/// assert_eq!(state_machine.unset_truth::<Counter>().unwrap().0, 400);
+/// ```
+///
+/// Truths that are `Send` but not `Sync` can not be set:
+///
+/// ```compile_fail
+/// use std::cell::Cell;
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct Visits(Cell<u32>);
+///
+/// let state_machine = SharedStateMachine::new();
+/// state_machine.set_truth(Visits(Cell::new(0)));
+/// ```
+#[derive(Default)]
+pub struct SharedStateMachine {
+    inner: Mutex<Inner>,
+    released: Condvar
+}
+
+impl SharedStateMachine {
+    /// Creates a new state machine with an empty state.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks if a `SendTransition` can be run right now.
+    ///
+    /// This function will check if none of the truths the transition needs are held by a running transition,
+    /// if the required truths for the transition are in the state, and if the truths it requires to be absent are not.
+    pub fn can_run(&self, transition: &SendTransition) -> bool {
+        let inner = self.lock();
+        inner.blocked_by(transition).is_none()
+            && transition.signature.check_with(|id| inner.truths.contains_key(id)).is_ok()
+    }
+
+    /// Runs a `SendTransition`, waiting for the truths it needs to be released.
+    ///
+    /// Once no running transition holds any of the truths the transition needs, this function will run it
+    /// if all the required truths are in the state. If the transition requires a truth that is not in the state,
+    /// or a truth to be absent that is in the state, this function will return an error.
+    ///
+    /// Like `StateMachine::run`, the transition is run atomically.
+    pub fn run(&self, transition: &SendTransition) -> Result<(),TransitionCallError> {
+        let inner = self.wait_while(|inner| inner.blocked_by(transition).is_some());
+        self.run_locked(inner, transition)
+    }
+
+    /// Runs a `SendTransition` if none of the truths it needs are held by a running transition.
+    ///
+    /// Unlike `run`, this function does not wait. If a truth the transition needs is held,
+    /// it will return `TransitionError::TruthHeld` without running the transition.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    ///
+    /// #[derive(Truth,Debug)]
+    /// struct Counter(i32);
+    ///
+    /// fn increment(counter: &mut Counter) {
+    ///     counter.0 += 1;
+    /// }
+    ///
+    /// let transition = increment.into_send_transition().unwrap();
+    ///
+    /// let state_machine = SharedStateMachine::new();
+    /// state_machine.set_truth(Counter(0));
+    /// state_machine.try_run(&transition).unwrap();
+    ///
+    /// assert_eq!(state_machine.unset_truth::<Counter>().unwrap().0, 1);
+    /// assert!(matches!(
+    ///     state_machine.try_run(&transition),
+    ///     Err(TransitionCallError::TransitionError(TransitionError::MissingTruth(_)))
+    /// ));
+    /// ```
+    pub fn try_run(&self, transition: &SendTransition) -> Result<(),TransitionCallError> {
+        let inner = self.lock();
+        if let Some(id) = inner.blocked_by(transition) {
+            return Err(TransitionError::TruthHeld(id).into());
+        }
+        self.run_locked(inner, transition)
+    }
+
+    /// Sets a truth in the state, waiting for it to be released by running transitions.
+    ///
+    /// This function will insert the truth into the state.
+    /// If a truth of the same type is already in the state, this function will replace it.
+    pub fn set_truth<T: Truth + Send + Sync + 'static>(&self, element: T) {
+        let id = Id::of::<T>();
+        let mut inner = self.wait_while(|inner| inner.held.contains(&id) || inner.forbidden.contains_key(&id));
+        inner.truths.insert(id, Box::new(element));
+    }
+
+    /// Checks if a truth is in the state, waiting for it to be released by running transitions.
+    pub fn has_truth<T: Truth + 'static>(&self) -> bool {
+        let id = Id::of::<T>();
+        self.wait_while(|inner| inner.held.contains(&id)).truths.contains_key(&id)
+    }
+
+    /// Returns the ids of all truths in the state that are not held by running transitions.
+    pub fn truths(&self) -> Vec<TruthId> {
+        self.lock().truths.keys().copied().collect()
+    }
+
+    /// Unsets a truth in the state, waiting for it to be released by running transitions.
+    ///
+    /// This function will remove the truth from the state and return it.
+    /// If the truth is not in the state, this function will return an error.
+    pub fn unset_truth<T: Truth + Send + Sync + 'static>(&self) -> Result<T, TransitionError> {
+        let id = Id::of::<T>();
+        self.wait_while(|inner| inner.held.contains(&id))
+            .truths
+            .remove(&id)
+            .map(|truth| *truth.downcast::<T>().expect("Invalid type stored for a truth in the state"))
+            .ok_or(TransitionError::MissingTruth(id))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait_while<F>(&self, condition: F) -> MutexGuard<'_, Inner>
+    where
+        F: FnMut(&mut Inner) -> bool
+    {
+        self.released.wait_while(self.lock(), condition).unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Checks and runs a transition whose truths are not held, holding them while it runs without the lock.
+    fn run_locked(&self, mut inner: MutexGuard<'_, Inner>, transition: &SendTransition) -> Result<(),TransitionCallError> {
+        transition.signature.check_with(|id| inner.truths.contains_key(id))?;
+        let truths = take(&mut inner.truths, transition);
+        inner.hold(transition);
+        drop(inner);
+
+        let mut hold = Hold { machine: self, transition, truths: Truths::new() };
+        let (result, truths) = execute(transition, truths);
+        hold.truths = truths;
+        result
+    }
+}
@@ -0,0 +1,77 @@
+use std::{any::Any, collections::HashMap, error::Error, fmt::{Debug, Display}};
+
+use crate::Id;
+
+/// A copy of the truths in a state machine at some point in time.
+///
+/// A snapshot is taken with `StateMachine::snapshot` and can be restored with `StateMachine::restore`.
+/// Its contents can not be inspected.
+pub struct Snapshot {
+    pub(crate) truths: HashMap<Id, Box<dyn Any>>
+}
+
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        let truths = self.truths.iter()
+            .map(|(id, truth)| (*id, id.clone_truth(truth.as_ref()).expect("Truth in a snapshot could not be cloned")))
+            .collect();
+
+        Self { truths }
+    }
+}
+
+impl Debug for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.truths.keys()).finish()
+    }
+}
+
+/// An Error that can occur when taking a snapshot of a state machine.
+///
+/// This error occurs when the state contains truths that can not be cloned.
+/// Every such truth is reported, so that no truth is silently left out of a snapshot.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth)]
+/// struct Connection();
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Connection());
+///
+/// let error = state_machine.snapshot().unwrap_err();
+///
+/// assert!(matches!(&error, SnapshotError::NotCloneable(ids) if ids == &[TruthId::of::<Connection>()]));
+/// assert_eq!(error.to_string(), format!("truths can not be cloned: `{}`", Connection::name()));
+/// ```
+pub enum SnapshotError {
+    NotCloneable(Vec<Id>)
+}
+
+impl Debug for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::NotCloneable(ids) => {
+                f.write_str("truths can not be cloned: ")?;
+                for (i, id) in ids.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "`{}`", id)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for SnapshotError {}
@@ -0,0 +1,414 @@
+use std::{any::Any, collections::{HashMap, HashSet}, sync::{Arc, Mutex, MutexGuard, PoisonError}};
+
+use crate::{Id, Truth};
+use crate::transition::keyed::{contains_keyed, get_keyed};
+
+/// Copies of some truths in the state, `None` for truths that were not in the state.
+pub(crate) type Captured = Vec<(Id, Option<Box<dyn Any>>)>;
+
+/// The truths inserted by a transaction, together with whether they replaced a truth.
+pub(crate) type Inserted = Vec<(Id, bool)>;
+
+/// Changes a truth in the truths of a state back.
+pub(crate) type Undo = dyn FnOnce(&mut HashMap<Id, Box<dyn Any>>);
+
+/// A change made to the state during a transaction.
+enum Change {
+    /// A truth was inserted, replacing the given truth.
+    Inserted(Id, Option<Box<dyn Any>>),
+    /// A truth was removed, and is restored from the given copy if it could be cloned.
+    Removed(Id, Option<Box<dyn Any>>),
+    /// A truth was changed in place, and is changed back by the given function.
+    Changed(Id, Box<Undo>),
+    /// Part of a truth was taken out in place, and is put back by the given function.
+    Taken(Id, Box<Undo>)
+}
+
+/// The changes made during a transaction, in the order they were made.
+type Journal = Vec<Change>;
+
+/// The changes made by a transaction that succeeded, which can still be undone with `State::undo`.
+pub(crate) struct Changes(Journal);
+
+impl Changes {
+    /// The truths that were inserted, in the order they were inserted, together with whether they replaced a truth.
+    /// Truths changed in place count as replaced.
+    pub(crate) fn inserted(&self) -> Inserted {
+        self.0.iter().filter_map(|change| match change {
+            Change::Inserted(id, replaced) => Some((*id, replaced.is_some())),
+            Change::Changed(id, _) => Some((*id, true)),
+            Change::Removed(..) | Change::Taken(..) => None
+        }).collect()
+    }
+
+    /// Every truth that was inserted, removed or changed.
+    pub(crate) fn ids(&self) -> HashSet<Id> {
+        self.0.iter().map(|change| match change {
+            Change::Inserted(id, _) | Change::Removed(id, _) | Change::Changed(id, _) | Change::Taken(id, _) => *id
+        }).collect()
+    }
+
+    /// The truths that were removed, but could not be cloned, so they can not be restored.
+    pub(crate) fn not_cloneable(&self) -> Vec<Id> {
+        self.0.iter().filter_map(|change| match change {
+            Change::Removed(id, None) => Some(*id),
+            _ => None
+        }).collect()
+    }
+
+    /// The truths that were removed, or of which a part was taken out.
+    pub(crate) fn removed(&self) -> Vec<Id> {
+        self.0.iter().filter_map(|change| match change {
+            Change::Removed(id, _) | Change::Taken(id, _) => Some(*id),
+            Change::Inserted(..) | Change::Changed(..) => None
+        }).collect()
+    }
+}
+
+/// The truths reserved by pending async transitions, shared with their reservations so that they can release them.
+///
+/// The reservations are sent to other threads together with their pending transitions, so they are shared through a mutex.
+#[derive(Default)]
+struct Reservations {
+    ids: HashSet<Id>,
+    /// The reservations that were released, whose taken truths can be forgotten.
+    released: Vec<usize>
+}
+
+type Reserved = Arc<Mutex<Reservations>>;
+
+fn lock(reserved: &Reserved) -> MutexGuard<'_, Reservations> {
+    reserved.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The truths stored in a state machine.
+///
+/// While a transaction is open, every truth inserted into the state is recorded together with the truth it replaced,
+/// every truth removed from the state together with a copy of it, and every truth changed in place together with
+/// a function that changes it back, so that the changes can be undone if the transaction fails.
+/// 
+/// While a transition selected by key runs, the key its keyed truths are taken under is stored with the state as well.
+///
+/// The truths taken by pending async transitions are kept by the state, so that they can be put back if the transition fails,
+/// without sending them to the thread the transition is polled on.
+#[derive(Default)]
+pub(crate) struct State {
+    truths: HashMap<Id, Box<dyn Any>>,
+    journal: Option<Journal>,
+    reserved: Reserved,
+    reservations: usize,
+    taken: HashMap<usize, Changes>,
+    keys: HashMap<Id, Box<dyn Any>>
+}
+
+/// Truths reserved by a pending async transition, released when the reservation is dropped.
+pub(crate) struct Reservation {
+    index: usize,
+    ids: Vec<Id>,
+    reserved: Reserved
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut reserved = lock(&self.reserved);
+        for id in &self.ids {
+            reserved.ids.remove(id);
+        }
+        reserved.released.push(self.index);
+    }
+}
+
+impl State {
+    pub(crate) fn contains_key(&self, id: &Id) -> bool {
+        self.truths.contains_key(id)
+    }
+
+    pub(crate) fn ids(&self) -> impl Iterator<Item = &Id> {
+        self.truths.keys()
+    }
+
+    pub(crate) fn get(&self, id: &Id) -> Option<&dyn Any> {
+        self.truths.get(id).map(|truth| truth.as_ref())
+    }
+
+    pub(crate) fn get_mut(&mut self, id: &Id) -> Option<&mut dyn Any> {
+        self.truths.get_mut(id).map(|truth| truth.as_mut())
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Id, &dyn Any)> {
+        self.truths.iter().map(|(id, truth)| (id, truth.as_ref()))
+    }
+
+    /// Removes a truth from the state.
+    ///
+    /// While a transaction is open, a copy of the truth is recorded if it can be cloned, so that it can be restored.
+    pub(crate) fn remove(&mut self, id: &Id) -> Option<Box<dyn Any>> {
+        let truth = self.truths.remove(id)?;
+        if let Some(journal) = &mut self.journal {
+            journal.push(Change::Removed(*id, id.clone_truth(truth.as_ref())));
+        }
+        Some(truth)
+    }
+
+    /// Inserts a new truth into the state.
+    pub(crate) fn insert(&mut self, id: Id, truth: Box<dyn Any>) {
+        let replaced = self.truths.insert(id, truth);
+        if let Some(journal) = &mut self.journal {
+            journal.push(Change::Inserted(id, replaced));
+        }
+    }
+
+    /// Records that a truth was changed in place, with a function that changes it back.
+    pub(crate) fn changed(&mut self, id: Id, undo: Box<Undo>) {
+        if let Some(journal) = &mut self.journal {
+            journal.push(Change::Changed(id, undo));
+        }
+    }
+
+    /// Records that part of a truth was taken out in place, with a function that puts it back.
+    pub(crate) fn taken(&mut self, id: Id, undo: Box<Undo>) {
+        if let Some(journal) = &mut self.journal {
+            journal.push(Change::Taken(id, undo));
+        }
+    }
+
+    /// Puts a truth that was taken out of the state, but not consumed, back into the state.
+    ///
+    /// Unlike `insert`, this is not recorded. If the transaction fails, the truth is set back to the copy
+    /// that was recorded when it was removed, which also undoes the changes made to it through a `&mut` reference.
+    pub(crate) fn put_back(&mut self, id: Id, truth: Box<dyn Any>) {
+        self.truths.insert(id, truth);
+    }
+
+    /// Clones every truth in the state.
+    ///
+    /// Returns the ids of all truths that can not be cloned as the error.
+    pub(crate) fn try_clone(&self) -> Result<HashMap<Id, Box<dyn Any>>, Vec<Id>> {
+        let mut truths = HashMap::with_capacity(self.truths.len());
+        let mut not_cloneable = Vec::new();
+
+        for (id, truth) in &self.truths {
+            match id.clone_truth(truth.as_ref()) {
+                Some(truth) => { truths.insert(*id, truth); },
+                None => not_cloneable.push(*id)
+            }
+        }
+
+        if not_cloneable.is_empty() {
+            Ok(truths)
+        } else {
+            Err(not_cloneable)
+        }
+    }
+
+    /// Clones the given truths.
+    ///
+    /// Returns the ids of all given truths in the state that can not be cloned as the error.
+    pub(crate) fn capture(&self, ids: &HashSet<Id>) -> Result<Captured, Vec<Id>> {
+        let mut captured = Vec::with_capacity(ids.len());
+        let mut not_cloneable = Vec::new();
+
+        for id in ids {
+            match self.truths.get(id) {
+                Some(truth) => match id.clone_truth(truth.as_ref()) {
+                    Some(truth) => captured.push((*id, Some(truth))),
+                    None => not_cloneable.push(*id)
+                },
+                None => captured.push((*id, None))
+            }
+        }
+
+        if not_cloneable.is_empty() {
+            Ok(captured)
+        } else {
+            Err(not_cloneable)
+        }
+    }
+
+    /// Sets the captured truths in the state again, and removes the truths that were captured as missing.
+    pub(crate) fn apply(&mut self, captured: &Captured) {
+        for (id, truth) in captured {
+            match truth {
+                Some(truth) => {
+                    let truth = id.clone_truth(truth.as_ref()).expect("Captured truth could not be cloned");
+                    self.insert(*id, truth);
+                },
+                None => { self.truths.remove(id); }
+            }
+        }
+    }
+
+    /// Takes every truth out of the state.
+    pub(crate) fn into_truths(self) -> HashMap<Id, Box<dyn Any>> {
+        self.truths
+    }
+
+    /// Replaces every truth in the state.
+    pub(crate) fn replace(&mut self, truths: HashMap<Id, Box<dyn Any>>) {
+        self.truths = truths;
+    }
+
+    /// Checks if a truth is reserved by a pending async transition.
+    pub(crate) fn is_reserved(&self, id: &Id) -> bool {
+        lock(&self.reserved).ids.contains(id)
+    }
+
+    /// Reserves the given truths until the returned reservation is dropped.
+    pub(crate) fn reserve(&mut self, ids: impl IntoIterator<Item = Id>) -> Reservation {
+        self.forget_released();
+        let ids: Vec<Id> = ids.into_iter().collect();
+        lock(&self.reserved).ids.extend(ids.iter().copied());
+        self.reservations += 1;
+        Reservation { index: self.reservations, ids, reserved: self.reserved.clone() }
+    }
+
+    /// Keeps the truths taken by the pending async transition holding the given reservation, until it is released.
+    pub(crate) fn keep_taken(&mut self, reservation: &Reservation, taken: Changes) {
+        self.taken.insert(reservation.index, taken);
+    }
+
+    /// Releases the given reservation, and returns the truths taken by its transition.
+    ///
+    /// Returns `None` if the reservation was made by another state.
+    pub(crate) fn release(&mut self, reservation: Reservation) -> Option<Changes> {
+        let taken = Arc::ptr_eq(&reservation.reserved, &self.reserved)
+            .then(|| self.taken.remove(&reservation.index))
+            .flatten();
+        drop(reservation);
+        self.forget_released();
+        taken
+    }
+
+    /// Forgets the truths taken by pending async transitions that were dropped.
+    fn forget_released(&mut self) {
+        let released = std::mem::take(&mut lock(&self.reserved).released);
+        for index in released {
+            self.taken.remove(&index);
+        }
+    }
+
+    /// Runs the given function with the given key selected for the keyed truths with the given id.
+    pub(crate) fn with_key<R>(&mut self, id: Id, key: Box<dyn Any>, func: impl FnOnce(&mut State) -> R) -> R {
+        let previous = self.keys.insert(id, key);
+        let result = func(self);
+        match previous {
+            Some(key) => { self.keys.insert(id, key); },
+            None => { self.keys.remove(&id); }
+        }
+        result
+    }
+
+    /// Returns the key selected for the keyed truths with the given id, if there is one.
+    pub(crate) fn key(&self, id: &Id) -> Option<&dyn Any> {
+        self.keys.get(id).map(|key| key.as_ref())
+    }
+
+    /// Runs the given function as a transaction.
+    ///
+    /// If the function fails, every truth it inserted is removed again and the truths they replaced are restored,
+    /// every truth it removed is restored and every truth it changed in place is changed back.
+    /// Truths are restored from copies, so a truth that can not be cloned and was consumed by a transition function is lost,
+    /// and changes made to it through a `&mut` reference are kept. Fallible transitions are only run if
+    /// `Signature::check_restorable` accepts the state, so that they are restored exactly.
+    ///
+    /// Nested transactions are part of the outermost transaction.
+    pub(crate) fn transaction<R,E>(&mut self, func: impl FnOnce(&mut State) -> Result<R,E>) -> Result<R,E> {
+        self.transaction_inserted(func).map(|(result, _)| result)
+    }
+
+    /// Runs the given function as a transaction, like `transaction`.
+    ///
+    /// If the function succeeds, the truths it inserted are returned as well, in the order they were inserted,
+    /// together with whether they replaced a truth. Truths changed in place count as replaced.
+    /// A nested transaction returns no truths.
+    pub(crate) fn transaction_inserted<R,E>(&mut self, func: impl FnOnce(&mut State) -> Result<R,E>) -> Result<(R, Inserted),E> {
+        self.transaction_changes(func).map(|(result, changes)| (result, changes.inserted()))
+    }
+
+    /// Runs the given function as a transaction, like `transaction`.
+    ///
+    /// If the function succeeds, the changes it made are returned as well, so that they can be undone later.
+    /// A nested transaction returns no changes.
+    pub(crate) fn transaction_changes<R,E>(&mut self, func: impl FnOnce(&mut State) -> Result<R,E>) -> Result<(R, Changes),E> {
+        if self.journal.is_some() {
+            return func(self).map(|result| (result, Changes(Vec::new())));
+        }
+
+        self.journal = Some(Vec::new());
+        let result = func(self);
+        let journal = self.journal.take().unwrap_or_default();
+
+        match result {
+            Ok(result) => Ok((result, Changes(journal))),
+            Err(e) => {
+                self.undo(Changes(journal));
+                Err(e)
+            }
+        }
+    }
+
+    /// Undoes the given changes, in reverse order.
+    pub(crate) fn undo(&mut self, Changes(journal): Changes) {
+        for change in journal.into_iter().rev() {
+            match change {
+                Change::Inserted(id, Some(truth)) | Change::Removed(id, Some(truth)) => { self.truths.insert(id, truth); },
+                Change::Inserted(id, None) => { self.truths.remove(&id); },
+                Change::Removed(_, None) => {},
+                Change::Changed(_, undo) | Change::Taken(_, undo) => undo(&mut self.truths)
+            }
+        }
+    }
+}
+
+/// A read-only view of the truths in a state machine.
+///
+/// A view is passed to the guards of transitions, so that they can inspect the state without consuming any truths.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth)]
+/// struct A(i32);
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(A(5));
+///
+/// let view = state_machine.view();
+///
+/// assert!(view.has::<A>());
+/// assert_eq!(view.get::<A>().map(|a| a.0), Some(5));
+/// ```
+#[derive(Clone, Copy)]
+pub struct StateView<'s> {
+    state: &'s State
+}
+
+impl<'s> StateView<'s> {
+    pub(crate) fn new(state: &'s State) -> Self {
+        Self { state }
+    }
+
+    /// Returns a reference to the truth of the given type, if it is in the state.
+    pub fn get<T: Truth + 'static>(&self) -> Option<&'s T> {
+        self.state.get(&Id::of::<T>())
+            .map(|truth| truth.downcast_ref::<T>().expect("Invalid type stored for a truth in the state"))
+    }
+
+    /// Checks if a truth of the given type is in the state.
+    pub fn has<T: Truth + 'static>(&self) -> bool {
+        self.state.contains_key(&Id::of::<T>())
+    }
+
+    /// Returns a reference to the truth of the given type stored under the given key, if it is in the state.
+    pub fn get_keyed<K: Ord + Clone + 'static, T: Truth + 'static>(&self, key: &K) -> Option<&'s T> {
+        get_keyed::<K,T>(self.state, key)
+    }
+
+    /// Checks if a truth of the given type is stored in the state under the given key.
+    pub fn has_keyed<K: Ord + Clone + 'static, T: Truth + 'static>(&self, key: &K) -> bool {
+        contains_keyed::<K,T>(self.state, key)
+    }
+}
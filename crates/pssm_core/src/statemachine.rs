@@ -1,8 +1,12 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 
-use crate::{State, TransitionCallError, Truth};
-use crate::transition::{InvalidTransitionError, IntoTransitionOnce, IntoTransitionOnceParameterized, Transition, TransitionError, TransitionMut, TransitionOnce};
-use crate::transition::function::TransitionInput;
+use crate::{Id, Snapshot, SnapshotError, State, StateView, TransitionCallError, Truth, TruthId};
+use crate::history::{History, Step};
+use crate::observer::Observers;
+use crate::state::Inserted;
+use crate::transition::{CompletedTransition, InvalidTransitionError, IntoAsyncTransition, IntoTransitionOnce, IntoTransitionOnceParameterized, PendingTransition, Signature, Transition, TransitionError, TransitionMut, TransitionOnce};
+use crate::transition::function::{take_truth, TransitionInput};
+use crate::transition::keyed::{contains_keyed, insert_keyed, take_keyed, Keyed};
 
 /// A state machine that has a state and can run transitions.
 /// 
@@ -47,7 +51,9 @@ use crate::transition::function::TransitionInput;
 /// assert_eq!(a.0, 15);
 /// ```
 pub struct StateMachine {
-    state: State
+    state: State,
+    history: Option<History>,
+    observers: Observers
 }
 
 impl StateMachine {
@@ -62,13 +68,21 @@ impl StateMachine {
     /// ```
     pub fn new() -> Self {
         Self {
-            state: HashMap::new(),
+            state: State::default(),
+            history: None,
+            observers: Observers::default()
         }
     }
 
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_state(state: State) -> Self {
+        Self { state, history: None, observers: Observers::default() }
+    }
+
     /// Checks if a transition can be run.
     /// 
-    /// This function will check if the required truths for the transition are in the state.
+    /// This function will check if the required truths for the transition are in the state, along with one truth
+    /// of each of its alternatives, and if the truths it requires to be absent are not.
     /// 
     /// If the `IntoTransitionOnce` object can not be converted into a `TransitionOnce`, this function will return an error.
     /// 
@@ -91,17 +105,22 @@ impl StateMachine {
     /// assert!(state_machine.can_run(&consume_a).unwrap());
     /// ```
     #[allow(private_bounds)]
-    pub fn can_run<'a,T,In>(&self, _: &T) -> Result<bool,InvalidTransitionError>
+    pub fn can_run<'a,T,In,M>(&self, _: &T) -> Result<bool,InvalidTransitionError>
     where 
-        In: TransitionInput,
-        T: IntoTransitionOnce<'a,In>
+        In: TransitionInput<M>,
+        T: IntoTransitionOnce<'a,(In,M)>
     {
-        Ok(In::required()?.iter().all(|id| self.state.contains_key(id)))
+        Ok(In::required()?.iter().all(|id| self.state.contains_key(id))
+            && In::alternatives().iter().all(|ids| ids.iter().any(|id| self.state.contains_key(id)))
+            && In::forbidden().iter().all(|id| !self.state.contains_key(id)))
     }
 
     /// Checks if a `Transition` can be run.
     /// 
-    /// This function will check if the required truths for the `Transition` are in the state.
+    /// This function will check if the required truths for the `Transition` are in the state,
+    /// if the truths it requires to be absent are not, and if its guards accept the state.
+    /// No truths are taken out of the state to do so. A chain is not reported as runnable if a later step has guards,
+    /// since they can only be checked after the earlier steps ran.
     /// 
     /// # Examples
     /// 
@@ -123,12 +142,15 @@ impl StateMachine {
     /// assert!(state_machine.can_run_transition(&transition));
     /// ```
     pub fn can_run_transition(&self, transition: &Transition) -> bool {
-        transition.requires().iter().all(|id| self.state.contains_key(id))
+        !transition.deferred_guards && transition.check(&self.state).is_ok()
     }
     
     /// Checks if a `TransitionMut` can be run.
     /// 
-    /// This function will check if the required truths for the `TransitionMut` are in the state.
+    /// This function will check if the required truths for the `TransitionMut` are in the state,
+    /// if the truths it requires to be absent are not, and if its guards accept the state.
+    /// No truths are taken out of the state to do so. A chain is not reported as runnable if a later step has guards,
+    /// since they can only be checked after the earlier steps ran.
     /// 
     /// # Examples
     /// 
@@ -150,12 +172,15 @@ impl StateMachine {
     /// assert!(state_machine.can_run_transition_mut(&transition));
     /// ```
     pub fn can_run_transition_mut(&self, transition: &TransitionMut) -> bool {
-        transition.requires().iter().all(|id| self.state.contains_key(id))
+        !transition.deferred_guards && transition.check(&self.state).is_ok()
     }
 
     /// Checks if a `TransitionOnce` can be run.
     /// 
-    /// This function will check if the required truths for the `TransitionOnce` are in the state.
+    /// This function will check if the required truths for the `TransitionOnce` are in the state,
+    /// if the truths it requires to be absent are not, and if its guards accept the state.
+    /// No truths are taken out of the state to do so. A chain is not reported as runnable if a later step has guards,
+    /// since they can only be checked after the earlier steps ran.
     /// 
     /// # Examples
     /// 
@@ -177,15 +202,24 @@ impl StateMachine {
     /// assert!(state_machine.can_run_transition_once(&transition));
     /// ```
     pub fn can_run_transition_once(&self, transition: &TransitionOnce) -> bool {
-        transition.requires().iter().all(|id| self.state.contains_key(id))
+        !transition.deferred_guards && transition.check(&self.state).is_ok()
     }
 
     /// Runs a transition.
     /// 
     /// This function will run the transition if all the required truths are in the state.
-    /// If the transition requires a truth that is not in the state, this function will return an error.
+    /// If the transition requires a truth that is not in the state, a truth to be absent that is in the state,
+    /// or if one of its guards rejects the state, this function will return an error.
+    /// 
+    /// The transition is run atomically. If it fails after it already changed the state, for example in the second
+    /// step of a chained transition, every truth it inserted is removed again, and every truth it took, replaced or changed
+    /// is set back to the value it had before. The truths are restored from copies, so a fallible transition, see
+    /// `Signature::fallible`, is only run if every truth in the state it can take by value or change through a `&mut`
+    /// reference can be cloned. Otherwise, this function will return `TransitionCallError::NotRestorable` without
+    /// changing the state.
     /// 
     /// If the `IntoTransitionOnce` object can not be converted into a `TransitionOnce`, this function will return an error.
+    /// If a fallible transition function returns an error, this function will return it as `TransitionCallError::Failed`.
     /// 
     /// # Examples
     /// 
@@ -208,26 +242,132 @@ impl StateMachine {
     /// 
     /// assert_eq!(a.0, 6);
     /// ```
+    /// 
+    /// When the second step of a chained transition fails, the changes of the first step are undone.
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Clone,Debug)]
+    /// #[truth(clone)]
+    /// struct Balance(i32);
+    /// 
+    /// #[derive(Truth,Debug)]
+    /// struct Attempt();
+    /// 
+    /// fn attempt() -> Attempt {
+    ///     Attempt()
+    /// }
+    /// 
+    /// fn withdraw(balance: &mut Balance, Param(amount): Param<i32>) -> Result<(), String> {
+    ///     if balance.0 < amount {
+    ///         return Err(format!("can not withdraw {} from {}", amount, balance.0));
+    ///     }
+    ///     balance.0 -= amount;
+    ///     Ok(())
+    /// }
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(Balance(10));
+    /// 
+    /// let transition = attempt.and_then_once(into_transition_once_with!(withdraw, 20).unwrap()).unwrap();
+    /// let result = state_machine.run(transition);
+    /// 
+    /// assert!(matches!(result, Err(TransitionCallError::Failed(_))));
+    /// assert!(!state_machine.has_truth::<Attempt>());
+    /// assert_eq!(state_machine.unset_truth::<Balance>().unwrap().0, 10);
+    /// ```
+    /// 
+    /// Truths that were taken by value or changed through a `&mut` reference before the failure are restored as well.
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Clone,Debug)]
+    /// #[truth(clone)]
+    /// struct Order(u32);
+    /// 
+    /// #[derive(Truth,Clone,Debug)]
+    /// #[truth(clone)]
+    /// struct Balance(i32);
+    /// 
+    /// #[derive(Truth,Debug)]
+    /// struct Paid(u32);
+    /// 
+    /// fn pay(order: Order, balance: &mut Balance) -> Paid {
+    ///     balance.0 -= 5;
+    ///     Paid(order.0)
+    /// }
+    /// 
+    /// fn confirm(_: &Paid) -> Result<(), String> {
+    ///     Err("payment declined".to_string())
+    /// }
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(Order(1));
+    /// state_machine.set_truth(Balance(10));
+    /// 
+    /// let result = state_machine.run(pay.and_then(confirm).unwrap());
+    /// 
+    /// assert!(matches!(result, Err(TransitionCallError::Failed(_))));
+    /// assert!(!state_machine.has_truth::<Paid>());
+    /// assert_eq!(state_machine.unset_truth::<Order>().unwrap().0, 1);
+    /// assert_eq!(state_machine.unset_truth::<Balance>().unwrap().0, 10);
+    /// ```
+    /// 
+    /// A fallible transition that would take or change a truth that can not be cloned is not run at all.
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Debug)]
+    /// struct A(i32);
+    /// 
+    /// #[derive(Truth,Debug)]
+    /// struct B(i32);
+    /// 
+    /// fn a_to_b(a: A) -> B {
+    ///     B(a.0)
+    /// }
+    /// 
+    /// fn bump(a: &mut A) {
+    ///     a.0 += 1;
+    /// }
+    /// 
+    /// fn fail_b(_: &B) -> Result<(), String> {
+    ///     Err("declined".to_string())
+    /// }
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(A(100));
+    /// state_machine.set_truth(B(0));
+    /// 
+    /// let result = state_machine.run(a_to_b.and_then(fail_b).unwrap());
+    /// assert!(matches!(result, Err(TransitionCallError::NotRestorable(SnapshotError::NotCloneable(ref ids))) if ids == &[TruthId::of::<A>()]));
+    /// 
+    /// let result = state_machine.run(bump.and_then(fail_b).unwrap());
+    /// assert!(matches!(result, Err(TransitionCallError::NotRestorable(_))));
+    /// 
+    /// assert_eq!(state_machine.unset_truth::<A>().unwrap().0, 100);
+    /// assert_eq!(state_machine.unset_truth::<B>().unwrap().0, 0);
+    /// ```
     pub fn run<'a,T,In>(&mut self, transition: T) -> Result<(),TransitionCallError>
     where 
         T: IntoTransitionOnce<'a,In>
     {
         let transition = transition.into_transition_once()?;
-        transition.requires().iter().try_for_each(|id| {
-            if self.state.contains_key(id) {
-                Ok(())
-            } else {
-                Err(TransitionError::MissingTruth(*id))
-            }
-        })?;
-        transition.run(&mut self.state)?;
-        Ok(())
+        let checked = transition.check(&self.state);
+        let TransitionOnce { func, signature, .. } = transition;
+        self.run_observed(&signature, checked, func)
     }
 
     /// Runs a transition with parameters.
     /// 
     /// This function will run the transition if all the required truths are in the state.
-    /// If the transition requires a truth that is not in the state, this function will return an error.
+    /// If the transition requires a truth that is not in the state, a truth to be absent that is in the state,
+    /// or if one of its guards rejects the state, this function will return an error.
+    /// 
+    /// Like `run`, the transition is run atomically.
     /// 
     /// If the `IntoTransitionOnceParameterized` object can not be converted into a `TransitionOnce`, this function will return an error.
     /// 
@@ -256,21 +396,76 @@ impl StateMachine {
         T: IntoTransitionOnceParameterized<'a,In,Param>
     {
         let transition = transition.into_transition_once_with(params)?;
-        transition.requires().iter().try_for_each(|id| {
-            if self.state.contains_key(id) {
-                Ok(())
-            } else {
-                Err(TransitionError::MissingTruth(*id))
-            }
-        })?;
-        transition.run(&mut self.state)?;
-        Ok(())
+        let checked = transition.check(&self.state);
+        let TransitionOnce { func, signature, .. } = transition;
+        self.run_observed(&signature, checked, func)
+    }
+
+    /// Runs a `Transition` by reference.
+    /// 
+    /// Like `run`, this function will check the transition before running it atomically, and return an error if it can not be run or fails.
+    /// The transition can be run again afterwards.
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Debug)]
+    /// struct A(i32);
+    /// 
+    /// fn increment(a: &mut A) {
+    ///     a.0 += 1;
+    /// }
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// let transition = increment.into_transition().unwrap();
+    /// 
+    /// assert!(state_machine.run_ref(&transition).is_err());
+    /// state_machine.set_truth(A(0));
+    /// state_machine.run_ref(&transition).unwrap();
+    /// state_machine.run_ref(&transition).unwrap();
+    /// 
+    /// assert_eq!(state_machine.unset_truth::<A>().unwrap().0, 2);
+    /// ```
+    pub fn run_ref(&mut self, transition: &Transition) -> Result<(),TransitionCallError> {
+        let checked = transition.check(&self.state);
+        self.run_observed(&transition.signature, checked, |state| transition.run(state))
+    }
+
+    /// Runs a `TransitionMut` by reference.
+    /// 
+    /// Like `run`, this function will check the transition before running it atomically, and return an error if it can not be run or fails.
+    /// The transition can be run again afterwards.
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Debug)]
+    /// struct A();
+    /// 
+    /// let mut count = 0;
+    /// let mut transition = (|_: &A| count += 1).into_transition_mut().unwrap();
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// assert!(state_machine.run_ref_mut(&mut transition).is_err());
+    /// state_machine.set_truth(A());
+    /// state_machine.run_ref_mut(&mut transition).unwrap();
+    /// 
+    /// drop(transition);
+    /// assert_eq!(count, 1);
+    /// ```
+    pub fn run_ref_mut(&mut self, transition: &mut TransitionMut) -> Result<(),TransitionCallError> {
+        let checked = transition.check(&self.state);
+        self.run_observed(&transition.signature, checked, |state| (transition.func)(state))
     }
 
     /// Runs a `TransitionOnce`.
     /// 
     /// This function will run the `TransitionOnce` if all the required truths are in the state.
-    /// If the `TransitionOnce` requires a truth that is not in the state, this function will panic.
+    /// If the `TransitionOnce` requires a truth that is not in the state, or if it fails, this function will panic.
     /// 
     /// # Examples
     /// 
@@ -291,13 +486,14 @@ impl StateMachine {
     /// ```
     pub fn run_unchecked(&mut self, transition: TransitionOnce)
     {
-        transition.run(&mut self.state).unwrap();
+        let TransitionOnce { func, signature, .. } = transition;
+        self.run_observed(&signature, Ok(()), func).unwrap();
     }
 
     /// Runs a `Transition`.
     /// 
     /// This function will run the `Transition` if all the required truths are in the state.
-    /// If the `Transition` requires a truth that is not in the state, this function will panic.
+    /// If the `Transition` requires a truth that is not in the state, or if it fails, this function will panic.
     /// 
     /// # Examples
     /// 
@@ -317,13 +513,13 @@ impl StateMachine {
     /// ```
     pub fn run_ref_unchecked(&mut self, transition: &Transition)
     {
-        transition.run(&mut self.state).unwrap();
+        self.run_observed(&transition.signature, Ok(()), |state| transition.run(state)).unwrap();
     }
     
     /// Runs a `TransitionMut`.
     /// 
     /// This function will run the `TransitionMut` if all the required truths are in the state.
-    /// If the `TransitionMut` requires a truth that is not in the state, this function will panic.
+    /// If the `TransitionMut` requires a truth that is not in the state, or if it fails, this function will panic.
     /// 
     /// # Examples
     /// 
@@ -347,7 +543,600 @@ impl StateMachine {
     /// ```
     pub fn run_ref_mut_unchecked(&mut self, transition: &mut TransitionMut)
     {
-        transition.run(&mut self.state).unwrap();
+        self.run_observed(&transition.signature, Ok(()), |state| (transition.func)(state)).unwrap();
+    }
+
+    /// Starts an async transition.
+    ///
+    /// This function will check the transition like `run`, take its inputs out of the state and return a `PendingTransition`,
+    /// a future that resolves once the transition function finished. Its result has to be inserted into the state
+    /// by passing the `CompletedTransition` the future resolves to to `complete`.
+    ///
+    /// Until then, every truth the transition can take, insert or forbids is reserved. Other transitions can run in the meantime,
+    /// but running a transition that takes or inserts a reserved truth fails with `TransitionError::TruthHeld`.
+    ///
+    /// The pending transition can be polled by any executor. It is `Send`, so it can be polled on another thread,
+    /// which requires the future returned by the transition function and its output to be `Send` as well.
+    /// The copies of the truths it took, kept to put them back if it fails, stay with the state machine.
+    /// Async transitions are not recorded in the history.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{future::Future, pin::pin, task::{Context, Poll, Waker}};
+    /// use pssm::prelude::*;
+    ///
+    /// fn block_on<F: Future>(future: F) -> F::Output {
+    ///     let mut future = pin!(future);
+    ///     let mut context = Context::from_waker(Waker::noop());
+    ///     loop {
+    ///         if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+    ///             return output;
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// #[derive(Truth,Debug)]
+    /// struct Request(u32);
+    ///
+    /// #[derive(Truth,Debug)]
+    /// struct Response(String);
+    ///
+    /// #[derive(Truth,Debug)]
+    /// struct Counter(i32);
+    ///
+    /// async fn send(request: Request) -> Response {
+    ///     Response(format!("response to {}", request.0))
+    /// }
+    ///
+    /// fn increment(counter: &mut Counter) {
+    ///     counter.0 += 1;
+    /// }
+    ///
+    /// fn respond() -> Response {
+    ///     Response("too early".to_string())
+    /// }
+    ///
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(Request(1));
+    /// state_machine.set_truth(Counter(0));
+    ///
+    /// let pending = state_machine.run_async(send).unwrap();
+    ///
+    /// state_machine.run(increment).unwrap();
+    /// assert!(matches!(
+    ///     state_machine.run(respond),
+    ///     Err(TransitionCallError::TransitionError(TransitionError::TruthHeld(_)))
+    /// ));
+    ///
+    /// let completed = block_on(pending);
+    /// state_machine.complete(completed).unwrap();
+    ///
+    /// assert_eq!(state_machine.unset_truth::<Response>().unwrap().0, "response to 1");
+    /// ```
+    ///
+    /// The pending transition can be sent to another thread, and completed once it resolved there:
+    ///
+    /// ```
+    /// use std::{future::Future, pin::pin, task::{Context, Poll, Waker}, thread};
+    /// use pssm::prelude::*;
+    ///
+    /// fn block_on<F: Future>(future: F) -> F::Output {
+    ///     let mut future = pin!(future);
+    ///     let mut context = Context::from_waker(Waker::noop());
+    ///     loop {
+    ///         if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+    ///             return output;
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// #[derive(Truth,Debug,Clone)]
+    /// #[truth(clone)]
+    /// struct Request(u32);
+    ///
+    /// #[derive(Truth,Debug)]
+    /// struct Response(u32);
+    ///
+    /// async fn send(request: Request) -> Result<Response, String> {
+    ///     if request.0 == 0 {
+    ///         return Err("empty request".to_string());
+    ///     }
+    ///     Ok(Response(request.0 * 2))
+    /// }
+    ///
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(Request(0));
+    ///
+    /// let pending = state_machine.run_async(send).unwrap();
+    /// let completed = thread::spawn(move || block_on(pending)).join().unwrap();
+    ///
+    /// assert!(state_machine.complete(completed).is_err());
+    /// assert_eq!(state_machine.view().get::<Request>().unwrap().0, 0);
+    /// ```
+    pub fn run_async<'a,T,In>(&mut self, transition: T) -> Result<PendingTransition<'a>,TransitionCallError>
+    where
+        T: IntoAsyncTransition<'a,In>
+    {
+        let transition = transition.into_async_transition()?;
+        self.observers.before(&transition.signature);
+
+        let checked = transition.signature.check(&self.state)
+            .map_err(TransitionCallError::from)
+            .and_then(|()| transition.signature.check_restorable(&self.state)
+                .map_err(|ids| TransitionCallError::NotRestorable(SnapshotError::NotCloneable(ids))));
+        if let Err(e) = checked {
+            self.observers.after(&transition.signature, Err(&e));
+            return Err(e);
+        }
+
+        let signature = transition.signature.clone();
+        let taken = self.taken_truths(&signature);
+        let reservation = self.state.reserve(signature.touches().into_iter().chain(signature.forbids.iter().copied()));
+        match transition.start(&mut self.state, reservation) {
+            Ok(pending) => {
+                self.notify(&taken, &[]);
+                Ok(pending)
+            },
+            Err(e) => {
+                let e = TransitionCallError::from(e);
+                self.observers.after(&signature, Err(&e));
+                Err(e)
+            }
+        }
+    }
+
+    /// Inserts the result of an async transition into the state, and releases the truths it reserved.
+    ///
+    /// If the async transition function failed, this function will return the error, and the truths the transition
+    /// took out of the state are put back. Like `run`, `run_async` does not start a fallible transition whose truths
+    /// could not be put back. See `run_async` for an example.
+    pub fn complete(&mut self, transition: CompletedTransition) -> Result<(),TransitionCallError> {
+        let CompletedTransition { finish, signature, reservation } = transition;
+        let taken = self.state.release(reservation);
+
+        let result = self.state.transaction_inserted(finish).map_err(TransitionCallError::from);
+        match &result {
+            Ok((_, inserted)) => self.notify(&[], inserted),
+            Err(_) => if let Some(taken) = taken {
+                let restored: Vec<(Id, bool)> = taken.removed().into_iter().map(|id| (id, false)).collect();
+                self.state.undo(taken);
+                self.notify(&[], &restored);
+            }
+        }
+        self.observers.after(&signature, result.as_ref().map(|_| ()));
+        result.map(|_| ())
+    }
+
+    /// Enables the history of this state machine, or changes its depth if it is already enabled.
+    /// 
+    /// While the history is enabled, every transition run by this state machine is recorded, so that it can be undone with `undo`
+    /// and redone with `redo`. At most `depth` transitions are kept to be undone. Running a transition after undoing one
+    /// forgets every transition that could be redone.
+    /// 
+    /// A transition is recorded as the truths it inserted, took out of the state or changed, before and after it ran.
+    /// These truths are cloned, so they have to be cloneable, for example by deriving `Truth` with `#[truth(clone)]`.
+    /// Running a transition that touches a truth which can not be cloned fails with `TransitionCallError::NotRecordable`,
+    /// without changing the state. Truths set or unset directly are not recorded.
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Clone)]
+    /// #[truth(clone)]
+    /// struct Text(String);
+    /// 
+    /// fn append(text: Text, Param(suffix): Param<&str>) -> Text {
+    ///     Text(text.0 + suffix)
+    /// }
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.enable_history(10);
+    /// state_machine.set_truth(Text("a".to_string()));
+    /// 
+    /// state_machine.run_with(append, ("b",)).unwrap();
+    /// state_machine.run_with(append, ("c",)).unwrap();
+    /// 
+    /// assert!(state_machine.undo());
+    /// assert_eq!(state_machine.view().get::<Text>().unwrap().0, "ab");
+    /// assert!(state_machine.undo());
+    /// assert_eq!(state_machine.view().get::<Text>().unwrap().0, "a");
+    /// assert!(!state_machine.undo());
+    /// 
+    /// assert!(state_machine.redo());
+    /// assert_eq!(state_machine.view().get::<Text>().unwrap().0, "ab");
+    /// ```
+    /// 
+    /// Only the truths a transition actually changed are recorded, so truths inserted through an `Option` output are undone as well.
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Clone)]
+    /// #[truth(clone)]
+    /// struct Order(u32);
+    /// 
+    /// #[derive(Truth,Clone)]
+    /// #[truth(clone)]
+    /// struct Approved(u32);
+    /// 
+    /// fn approve(order: &Order) -> Option<Approved> {
+    ///     (order.0 < 100).then(|| Approved(order.0))
+    /// }
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.enable_history(10);
+    /// state_machine.set_truth(Order(30));
+    /// 
+    /// state_machine.run(approve).unwrap();
+    /// assert!(state_machine.has_truth::<Approved>());
+    /// 
+    /// assert!(state_machine.undo());
+    /// assert!(!state_machine.has_truth::<Approved>());
+    /// assert!(state_machine.has_truth::<Order>());
+    /// ```
+    pub fn enable_history(&mut self, depth: usize) {
+        match &mut self.history {
+            Some(history) => history.set_depth(depth),
+            None => self.history = Some(History::new(depth))
+        }
+    }
+
+    /// Disables the history of this state machine, and forgets every recorded transition.
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Undoes the last recorded transition.
+    /// 
+    /// Every truth the transition touched is set back to the value it had before the transition ran.
+    /// Returns false if there is no transition to undo. See `enable_history` for an example.
+    pub fn undo(&mut self) -> bool {
+        match self.history.as_mut().and_then(History::undo) {
+            Some(step) => {
+                self.state.apply(&step.before);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Redoes the last undone transition.
+    /// 
+    /// Every truth the transition touched is set to the value it had after the transition ran.
+    /// Returns false if there is no transition to redo. See `enable_history` for an example.
+    pub fn redo(&mut self) -> bool {
+        match self.history.as_mut().and_then(History::redo) {
+            Some(step) => {
+                self.state.apply(&step.after);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Checks if there is a recorded transition to undo.
+    pub fn can_undo(&self) -> bool {
+        self.history.as_ref().is_some_and(History::can_undo)
+    }
+
+    /// Checks if there is an undone transition to redo.
+    pub fn can_redo(&self) -> bool {
+        self.history.as_ref().is_some_and(History::can_redo)
+    }
+
+    /// The truths a transition with the given signature may change, which have to be cloneable while the history is enabled,
+    /// none if the history is disabled.
+    /// 
+    /// The truths in the state the transition drains are included, as they are taken out of the state whatever their type.
+    fn recorded_truths(&self, signature: &Signature) -> HashSet<Id> {
+        match self.history {
+            Some(_) => signature.touches().into_iter().chain(self.drained_truths(signature)).collect(),
+            None => HashSet::new()
+        }
+    }
+
+    /// Runs a transition as a transaction, and records the truths it changed in the history if it is enabled.
+    /// 
+    /// The given truths are checked to be cloneable before the transition runs, so that it fails without changing the state
+    /// if it could not be recorded.
+    fn run_recorded<F>(&mut self, truths: HashSet<Id>, func: F) -> Result<Inserted, TransitionCallError>
+    where 
+        F: FnOnce(&mut State) -> Result<(), TransitionError>
+    {
+        let Some(history) = &mut self.history else {
+            return Ok(self.state.transaction_inserted(func)?.1);
+        };
+
+        let not_recordable = |ids| TransitionCallError::NotRecordable(SnapshotError::NotCloneable(ids));
+        self.state.capture(&truths).map_err(not_recordable)?;
+
+        let ((), changes) = self.state.transaction_changes(func)?;
+        let inserted = changes.inserted();
+        let changed = changes.ids();
+
+        let not_cloneable = changes.not_cloneable();
+        if !not_cloneable.is_empty() {
+            self.state.undo(changes);
+            return Err(not_recordable(not_cloneable));
+        }
+
+        let after = match self.state.capture(&changed) {
+            Ok(after) => after,
+            Err(ids) => {
+                self.state.undo(changes);
+                return Err(not_recordable(ids));
+            }
+        };
+
+        self.state.undo(changes);
+        let before = self.state.capture(&changed).map_err(not_recordable)?;
+        self.state.apply(&after);
+
+        history.record(Step { before, after });
+        Ok(inserted)
+    }
+
+    /// Runs a transition that was checked, and notifies the observers before and after it ran.
+    /// 
+    /// The observers of the truths the transition took out of the state and inserted into the state are notified as well.
+    fn run_observed<F>(&mut self, signature: &Signature, checked: Result<(), TransitionError>, func: F) -> Result<(), TransitionCallError>
+    where 
+        F: FnOnce(&mut State) -> Result<(), TransitionError>
+    {
+        self.observers.before(signature);
+
+        let result = checked.map_err(TransitionCallError::from).and_then(|()| {
+            signature.check_restorable(&self.state)
+                .map_err(|ids| TransitionCallError::NotRestorable(SnapshotError::NotCloneable(ids)))?;
+            let taken = self.taken_truths(signature);
+            let truths = self.recorded_truths(signature);
+            match self.run_recorded(truths, func) {
+                Ok(inserted) => {
+                    self.notify(&taken, &inserted);
+                    Ok(())
+                },
+                Err(e) => {
+                    let taken: Vec<Id> = taken.into_iter().filter(|id| !self.state.contains_key(id)).collect();
+                    self.notify(&taken, &[]);
+                    Err(e)
+                }
+            }
+        });
+
+        self.observers.after(signature, result.as_ref().map(|_| ()));
+        result
+    }
+
+    /// The truths in the state a transition with the given signature takes, none if no truth is observed.
+    fn taken_truths(&self, signature: &Signature) -> Vec<Id> {
+        if !self.observers.observes_truths() {
+            return Vec::new();
+        }
+
+        signature.consumes().into_iter()
+            .chain(signature.optional.iter().copied())
+            .filter(|id| self.state.contains_key(id))
+            .chain(self.drained_truths(signature))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// The truths in the state a transition with the given signature drains.
+    fn drained_truths(&self, signature: &Signature) -> Vec<Id> {
+        self.state.ids()
+            .filter(|id| !self.state.is_reserved(id) && id.tags().iter().any(|tag| signature.drains.contains(tag)))
+            .copied()
+            .collect()
+    }
+
+    /// Notifies the observers that the taken truths left the state, and that the inserted truths entered it.
+    /// 
+    /// A truth that replaced a truth of the same type which was not taken also notifies that the replaced truth left the state.
+    fn notify(&mut self, taken: &[Id], inserted: &[(Id, bool)]) {
+        for id in taken {
+            self.observers.unset(id);
+        }
+
+        let mut seen = HashSet::new();
+        for (id, replaced) in inserted {
+            if seen.insert(*id) && *replaced && !taken.contains(id) {
+                self.observers.unset(id);
+            }
+        }
+
+        for id in seen {
+            if let Some(truth) = self.state.get(&id) {
+                self.observers.set(&id, truth);
+            }
+        }
+    }
+
+    /// Calls the given function whenever a truth of the given type is set in the state.
+    /// 
+    /// The function is called with the new truth after `set_truth` and after every transition that inserted the truth.
+    /// If a transition inserted the truth multiple times, the function is only called for the truth that was left in the state.
+    /// Undoing, redoing and restoring a snapshot do not call it.
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// use std::{cell::RefCell, rc::Rc};
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Debug)]
+    /// struct Order(u32);
+    /// 
+    /// #[derive(Truth,Debug)]
+    /// struct Shipped(u32);
+    /// 
+    /// fn ship(order: Order) -> Shipped {
+    ///     Shipped(order.0)
+    /// }
+    /// 
+    /// let log = Rc::new(RefCell::new(Vec::new()));
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// let set_log = log.clone();
+    /// state_machine.on_set::<Shipped,_>(move |shipped| set_log.borrow_mut().push(format!("shipped {}", shipped.0)));
+    /// let unset_log = log.clone();
+    /// state_machine.on_unset::<Order,_>(move || unset_log.borrow_mut().push("order removed".to_string()));
+    /// 
+    /// state_machine.set_truth(Order(7));
+    /// state_machine.run(ship).unwrap();
+    /// 
+    /// assert_eq!(*log.borrow(), vec!["order removed", "shipped 7"]);
+    /// ```
+    pub fn on_set<T,F>(&mut self, mut callback: F)
+    where 
+        T: Truth + 'static,
+        F: FnMut(&T) + 'static
+    {
+        self.observers.add_set(Id::of::<T>(), Box::new(move |truth| {
+            callback(truth.downcast_ref::<T>().expect("Invalid type stored for a truth in the state"))
+        }));
+    }
+
+    /// Calls the given function whenever a truth of the given type leaves the state.
+    /// 
+    /// The function is called after `unset_truth`, when `set_truth` replaces the truth, and after every transition that took the truth
+    /// out of the state or replaced it. Borrowed truths do not leave the state. Undoing, redoing and restoring a snapshot do not call it.
+    /// See `on_set` for an example.
+    pub fn on_unset<T,F>(&mut self, callback: F)
+    where 
+        T: Truth + 'static,
+        F: FnMut() + 'static
+    {
+        self.observers.add_unset(Id::of::<T>(), Box::new(callback));
+    }
+
+    /// Calls the given function before every transition this state machine runs.
+    /// 
+    /// The function is called with the signature of the transition, whose `requires` and `produces` tell which truths it uses,
+    /// before the transition is checked. It is called by every `run` method and by `run_async`.
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// use std::{cell::RefCell, rc::Rc};
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Debug)]
+    /// struct A();
+    /// 
+    /// fn consume_a(_: A) {}
+    /// 
+    /// let runs = Rc::new(RefCell::new(Vec::new()));
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// let before = runs.clone();
+    /// state_machine.before_transition(move |signature| {
+    ///     before.borrow_mut().push(signature.requires().contains(&TruthId::of::<A>()));
+    /// });
+    /// let after = runs.clone();
+    /// state_machine.after_transition(move |_, result| after.borrow_mut().push(result.is_ok()));
+    /// 
+    /// assert!(state_machine.run(consume_a).is_err());
+    /// state_machine.set_truth(A());
+    /// state_machine.run(consume_a).unwrap();
+    /// 
+    /// assert_eq!(*runs.borrow(), vec![true, false, true, true]);
+    /// ```
+    pub fn before_transition<F>(&mut self, callback: F)
+    where 
+        F: FnMut(&Signature) + 'static
+    {
+        self.observers.add_before(Box::new(callback));
+    }
+
+    /// Calls the given function after every transition this state machine runs.
+    /// 
+    /// The function is called with the signature of the transition and its result, also if the transition could not be run.
+    /// For async transitions, it is called by `complete`, or by `run_async` if the transition could not be started.
+    /// See `before_transition` for an example.
+    pub fn after_transition<F>(&mut self, callback: F)
+    where 
+        F: FnMut(&Signature, Result<(), &TransitionCallError>) + 'static
+    {
+        self.observers.add_after(Box::new(callback));
+    }
+
+    /// Returns a read-only view of the state.
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth)]
+    /// struct A(i32);
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(A(1));
+    /// 
+    /// assert_eq!(state_machine.view().get::<A>().map(|a| a.0), Some(1));
+    /// ```
+    pub fn view(&self) -> StateView<'_> {
+        StateView::new(&self.state)
+    }
+
+    /// Takes a snapshot of the state.
+    /// 
+    /// The snapshot contains a copy of every truth in the state, and can be restored later with `restore`.
+    /// Only truths that can be cloned, for example by deriving `Truth` with `#[truth(clone)]`, can be captured.
+    /// If the state contains truths that can not be cloned, this function will return an error listing all of them.
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Clone)]
+    /// #[truth(clone)]
+    /// struct Counter(i32);
+    /// 
+    /// fn increment(counter: &mut Counter) {
+    ///     counter.0 += 1;
+    /// }
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(Counter(0));
+    /// 
+    /// let snapshot = state_machine.snapshot().unwrap();
+    /// state_machine.run(increment).unwrap();
+    /// assert_eq!(state_machine.view().get::<Counter>().unwrap().0, 1);
+    /// 
+    /// state_machine.restore(snapshot);
+    /// assert_eq!(state_machine.view().get::<Counter>().unwrap().0, 0);
+    /// ```
+    pub fn snapshot(&self) -> Result<Snapshot, SnapshotError> {
+        self.state.try_clone()
+            .map(|truths| Snapshot { truths })
+            .map_err(SnapshotError::NotCloneable)
+    }
+
+    /// Restores a snapshot of the state.
+    /// 
+    /// Every truth in the state is replaced by the truths in the snapshot.
+    /// Truths that were set after the snapshot was taken are dropped.
+    /// 
+    /// See `snapshot` for an example.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.state.replace(snapshot.truths);
+    }
+
+    /// Returns a serializable view of this state machine, using the truths in the given registry.
+    /// 
+    /// Every truth in the state has to be registered, otherwise serialization fails.
+    /// See `TruthRegistry` for an example.
+    /// 
+    /// This function is only available with the `serde` feature.
+    #[cfg(feature = "serde")]
+    pub fn serializable<'s>(&'s self, registry: &'s crate::TruthRegistry) -> crate::SerializableStateMachine<'s> {
+        crate::SerializableStateMachine { state: &self.state, registry }
     }
 
     /// Sets a truth in the state.
@@ -369,7 +1158,15 @@ impl StateMachine {
     /// assert!(state_machine.has_truth::<A>());
     /// ```
     pub fn set_truth<T: Truth + 'static>(&mut self, element: T) {
-        self.state.insert(T::id(), Box::new(element));
+        let id = Id::of::<T>();
+        if self.state.contains_key(&id) {
+            self.observers.unset(&id);
+        }
+
+        self.state.insert(id, Box::new(element));
+        if let Some(truth) = self.state.get(&id) {
+            self.observers.set(&id, truth);
+        }
     }
 
     /// Checks if a truth is in the state.
@@ -391,7 +1188,26 @@ impl StateMachine {
     /// assert!(state_machine.has_truth::<A>());
     /// ```
     pub fn has_truth<T: Truth + 'static>(&self) -> bool {
-        self.state.contains_key(&T::id())
+        self.state.contains_key(&Id::of::<T>())
+    }
+
+    /// Returns the ids of all truths in the state.
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth)]
+    /// struct A();
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(A());
+    /// 
+    /// assert_eq!(state_machine.truths().collect::<Vec<_>>(), vec![TruthId::of::<A>()]);
+    /// ```
+    pub fn truths(&self) -> impl Iterator<Item = TruthId> + '_ {
+        self.state.ids().copied()
     }
 
     /// Unsets a truth in the state.
@@ -414,6 +1230,66 @@ impl StateMachine {
     /// assert!(state_machine.unset_truth::<A>().is_ok());
     /// ```
     pub fn unset_truth<T: Truth + 'static>(&mut self) -> Result<T, TransitionError> {
-        T::try_take_from(&mut self.state)
+        let truth = take_truth::<T>(&mut self.state)?;
+        self.observers.unset(&Id::of::<T>());
+        Ok(*truth)
+    }
+
+    /// Sets a truth in the state under the given key.
+    /// 
+    /// If a truth of the same type is already stored under the same key, this function will replace it.
+    /// Truths of the same type stored under other keys are kept. See `Keyed` for an example.
+    /// 
+    /// Observers are not notified about keyed truths.
+    pub fn set_truth_keyed<K,T>(&mut self, key: K, element: T)
+    where 
+        K: Ord + Clone + 'static,
+        T: Truth + 'static
+    {
+        insert_keyed(&mut self.state, Keyed::new(key, element));
+    }
+
+    /// Checks if a truth of the given type is stored in the state under the given key.
+    pub fn has_truth_keyed<K,T>(&self, key: &K) -> bool
+    where 
+        K: Ord + Clone + 'static,
+        T: Truth + 'static
+    {
+        contains_keyed::<K,T>(&self.state, key)
+    }
+
+    /// Unsets the truth of the given type stored in the state under the given key.
+    /// 
+    /// This function will remove the truth from the state and return it.
+    /// If no such truth is in the state, this function will return an error.
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth)]
+    /// struct Session(&'static str);
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth_keyed(1, Session("alice"));
+    /// state_machine.set_truth_keyed(2, Session("bob"));
+    /// 
+    /// assert_eq!(state_machine.unset_truth_keyed::<i32, Session>(&1).unwrap().0, "alice");
+    /// assert!(state_machine.unset_truth_keyed::<i32, Session>(&1).is_err());
+    /// assert!(state_machine.has_truth_keyed::<i32, Session>(&2));
+    /// ```
+    pub fn unset_truth_keyed<K,T>(&mut self, key: &K) -> Result<T, TransitionError>
+    where 
+        K: Ord + Clone + 'static,
+        T: Truth + 'static
+    {
+        take_keyed::<K,T>(&mut self.state, Some(key)).map(|keyed| keyed.into_parts().1)
+    }
+}
+
+impl Default for StateMachine {
+    fn default() -> Self {
+        Self::new()
     }
 }
\ No newline at end of file
@@ -1,29 +1,136 @@
-use std::{collections::HashSet, fmt::Debug};
+use std::{collections::HashSet, error::Error, fmt::{Debug, Display}};
 
 use crate::{Id, transition::InvalidTransitionError};
 
-use super::{IntoTransition, IntoTransitionMut, IntoTransitionOnce, Transition, TransitionMut, TransitionOnce};
-
-fn combine_requirements(
-    mut requires1: HashSet<Id>,
-    produces1: HashSet<Id>,
-    requires2: HashSet<Id>,
-    mut produces2: HashSet<Id>
-) -> Result<(HashSet<Id>,HashSet<Id>),AndThenError> {
-    for id in requires1.intersection(&requires2) {
-        if !produces1.contains(id) {
-            return Err(AndThenError::ConflictingRequirements);
+use super::{check_guards, IntoTransition, IntoTransitionMut, IntoTransitionOnce, Signature, Transition, TransitionMut, TransitionOnce};
+
+/// Combines the signatures of two chained transitions, `guarded` tells whether the second transition has guards.
+fn combine_signatures(first: Signature, second: Signature, guarded: bool) -> Result<Signature,AndThenError> {
+    for id in first.requires.intersection(&second.requires) {
+        if !first.produces.contains(id) && !first.borrows.contains(id) {
+            return Err(AndThenError::ConflictingRequirements(*id));
         }
     }
 
-    requires1.extend(requires2.difference(&produces1).cloned());
-    produces2.extend(produces1.difference(&requires2).cloned());
+    for id in first.optional.intersection(&second.requires) {
+        if !first.produces.contains(id) {
+            return Err(AndThenError::ConflictingRequirements(*id));
+        }
+    }
+
+    if let Some(id) = second.forbids.intersection(&first.produces).next() {
+        return Err(AndThenError::ConflictingAbsence(*id));
+    }
+
+    let first_consumes = first.consumes();
+    let second_consumes = second.consumes();
+
+    let taken_by_first = |id: &Id| !first.produces.contains(id) && (first_consumes.contains(id) || first.optional.contains(id)
+        || first.alternatives.iter().flatten().any(|alternative| alternative == id && !first.borrows.contains(id))
+        || id.tags().iter().any(|tag| first.drains.contains(tag)));
+
+    let fallible = first.fallible || second.fallible || guarded
+        || second.requires.iter().chain(second.alternatives.iter().flatten()).any(taken_by_first)
+        || second.forbids.iter().any(|id| first.may_produce.contains(id));
+
+    let mut mutates = first.mutates.clone();
+    mutates.extend(second.mutates.difference(&first.produces).cloned());
+
+    let mut forbids = first.forbids;
+    forbids.extend(second.forbids.iter()
+        .filter(|id| !first_consumes.contains(id) && !first.optional.contains(id))
+        .cloned());
+
+    let mut requires = first.requires;
+    requires.extend(second.requires.difference(&first.produces).cloned());
+
+    let mut consumes = first_consumes;
+    consumes.extend(second_consumes.difference(&first.produces).cloned());
+
+    let second_alternatives: HashSet<Id> = second.alternatives.iter()
+        .flatten()
+        .filter(|id| !second.borrows.contains(id))
+        .cloned()
+        .collect();
+
+    let kept_by_second = |id: &&Id| !second_consumes.contains(id) && !second.optional.contains(id) && !second_alternatives.contains(id)
+        && !id.tags().iter().any(|tag| second.drains.contains(tag));
+
+    let mut produces = second.produces;
+    produces.extend(first.produces.iter()
+        .filter(kept_by_second)
+        .cloned());
+
+    let mut may_produce = second.may_produce;
+    may_produce.extend(first.may_produce.iter()
+        .filter(kept_by_second)
+        .cloned());
+    may_produce.retain(|id| !produces.contains(id));
+
+    let mut optional = first.optional;
+    optional.extend(second.optional);
+
+    let mut drains = first.drains;
+    drains.extend(second.drains);
 
-    Ok((requires1,produces2))
+    let mut alternatives = first.alternatives;
+    alternatives.extend(second.alternatives.into_iter()
+        .filter(|ids| !ids.iter().any(|id| first.produces.contains(id))));
+
+    let borrows = requires.difference(&consumes)
+        .chain(alternatives.iter().flatten().filter(|id| first.borrows.contains(id) || second.borrows.contains(id)))
+        .cloned()
+        .collect();
+
+    if let Some(id) = requires.intersection(&forbids).next() {
+        return Err(AndThenError::ConflictingAbsence(*id));
+    }
+
+    Ok(Signature { requires, borrows, mutates, optional, forbids, produces, may_produce, drains, alternatives, fallible })
 }
 
+/// An Error that can occur when chaining two transitions.
+/// 
+/// This error occurs when the second transition requires a truth that the first transition consumes,
+/// either as a required or as an optional input, without producing it again,
+/// or when one of the transitions requires a truth to be absent that the chain needs to be present at that point.
+/// Such a chain could never run to completion, so it is rejected before it can change any state.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth,Debug)]
+/// struct A();
+/// 
+/// fn maybe_consume_a(a: Option<A>) {
+///     println!("{:?}", a);
+/// }
+/// 
+/// fn consume_a(a: A) {
+///     println!("{:?}", a);
+/// }
+/// 
+/// fn read_a(a: &A) {
+///     println!("{:?}", a);
+/// }
+/// 
+/// let error = maybe_consume_a.and_then_once(consume_a).unwrap_err();
+/// assert!(matches!(error, AndThenError::ConflictingRequirements(id) if id == TruthId::of::<A>()));
+/// assert!(read_a.and_then_once(consume_a).is_ok());
+/// 
+/// fn insert_a(_: Absent<A>) -> A {
+///     A()
+/// }
+/// 
+/// let error = insert_a.and_then_once(insert_a).unwrap_err();
+/// assert!(matches!(error, AndThenError::ConflictingAbsence(id) if id == TruthId::of::<A>()));
+/// assert!(consume_a.and_then_once(insert_a).is_ok());
+/// ```
 pub enum AndThenError {
-    ConflictingRequirements,
+    ConflictingRequirements(Id),
+    ConflictingAbsence(Id),
     IntoTransitionError(InvalidTransitionError)
 }
 
@@ -36,8 +143,27 @@ impl From<InvalidTransitionError> for AndThenError {
 impl Debug for AndThenError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            AndThenError::ConflictingRequirements => write!(f, "Both transitions require the same input, but the first transition does not produce it."),
-            AndThenError::IntoTransitionError(e) => e.fmt(f)
+            AndThenError::IntoTransitionError(e) => Debug::fmt(e, f),
+            _ => Display::fmt(self, f)
+        }
+    }
+}
+
+impl Display for AndThenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AndThenError::ConflictingRequirements(id) => write!(f, "both transitions require truth `{}`, but the first transition consumes it without producing it again", id),
+            AndThenError::ConflictingAbsence(id) => write!(f, "truth `{}` is required to be absent, but the chained transitions need it to be present", id),
+            AndThenError::IntoTransitionError(_) => f.write_str("invalid transition")
+        }
+    }
+}
+
+impl Error for AndThenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AndThenError::ConflictingRequirements(_) | AndThenError::ConflictingAbsence(_) => None,
+            AndThenError::IntoTransitionError(e) => Some(e)
         }
     }
 }
@@ -73,7 +199,13 @@ pub trait AndThen<'a,InA> {
     /// This function will chain this transition with the given transition, creating a new transition that runs
     /// this transition followed by the given transition.
     /// 
-    /// If both transitions require the same input, but the first transition does not produce it, an error will be returned.
+    /// If the second transition requires a truth that the first transition consumes without producing it again, an error will be returned.
+    /// 
+    /// The guards of this transition are checked before the chain is run, the guards of the given transition
+    /// are checked after this transition has run. If they reject the state, the chain fails and is rolled back.
+    /// Since the guards of the given transition can only be checked while the chain runs, `StateMachine::can_run_transition`
+    /// and `TransitionDictionary::runnable_transitions` never report a chain as runnable if the given transition has guards.
+    /// The chain has the priority of this transition.
     /// 
     /// # Examples
     /// 
@@ -94,6 +226,36 @@ pub trait AndThen<'a,InA> {
     /// let mut state_machine = StateMachine::new();
     /// state_machine.run(insert_a.and_then(consume_a).unwrap());
     /// ```
+    /// 
+    /// A chain whose second transition has a guard is not reported as runnable, and fails if the guard rejects the state
+    /// after the first transition ran.
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Truth,Clone,Debug)]
+    /// #[truth(clone)]
+    /// struct Balance(i32);
+    /// 
+    /// fn deposit(balance: &mut Balance) {
+    ///     balance.0 += 5;
+    /// }
+    /// 
+    /// fn withdraw(balance: &mut Balance) {
+    ///     balance.0 -= 20;
+    /// }
+    /// 
+    /// let withdraw = withdraw.guard(|state| state.get::<Balance>().is_some_and(|b| b.0 >= 20)).unwrap();
+    /// let transition = deposit.and_then(withdraw).unwrap();
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(Balance(10));
+    /// 
+    /// assert!(!state_machine.can_run_transition(&transition));
+    /// let error = state_machine.run_ref(&transition).unwrap_err();
+    /// assert!(matches!(error, TransitionCallError::TransitionError(TransitionError::GuardRejected)));
+    /// assert_eq!(state_machine.unset_truth::<Balance>().unwrap().0, 10);
+    /// ```
     fn and_then<Next,InB>(self, next: Next) -> Result<Transition<'a>,AndThenError>
     where
         Next: IntoTransition<'a,InB>;
@@ -133,7 +295,13 @@ pub trait AndThenMut<'a,InA> {
     /// This function will chain this transition with the given transition, creating a new mutable transition that runs
     /// this transition followed by the given transition.
     /// 
-    /// If both transitions require the same input, but the first transition does not produce it, an error will be returned.
+    /// If the second transition requires a truth that the first transition consumes without producing it again, an error will be returned.
+    /// 
+    /// The guards of this transition are checked before the chain is run, the guards of the given transition
+    /// are checked after this transition has run. If they reject the state, the chain fails and is rolled back.
+    /// Since the guards of the given transition can only be checked while the chain runs, `StateMachine::can_run_transition`
+    /// and `TransitionDictionary::runnable_transitions` never report a chain as runnable if the given transition has guards.
+    /// The chain has the priority of this transition.
     /// 
     /// # Examples
     /// 
@@ -193,7 +361,13 @@ pub trait AndThenOnce<'a,InA> {
     /// This function will chain this transition with the given transition, creating a new transition that runs
     /// this transition followed by the given transition.
     /// 
-    /// If both transitions require the same input, but the first transition does not produce it, an error will be returned.
+    /// If the second transition requires a truth that the first transition consumes without producing it again, an error will be returned.
+    /// 
+    /// The guards of this transition are checked before the chain is run, the guards of the given transition
+    /// are checked after this transition has run. If they reject the state, the chain fails and is rolled back.
+    /// Since the guards of the given transition can only be checked while the chain runs, `StateMachine::can_run_transition`
+    /// and `TransitionDictionary::runnable_transitions` never report a chain as runnable if the given transition has guards.
+    /// The chain has the priority of this transition.
     /// 
     /// # Examples
     /// 
@@ -227,17 +401,20 @@ where
         let t1 = self.into_transition()?;
         let t2 = next.into_transition()?;
 
-        let (requires,produces) = combine_requirements(t1.requires,t1.produces,t2.requires,t2.produces)?;
+        let signature = combine_signatures(t1.signature, t2.signature, !t2.guards.is_empty())?;
+        let deferred_guards = t1.deferred_guards || t2.deferred_guards || !t2.guards.is_empty();
 
-        Ok(Transition::new(
-            move |args| {
+        Ok(Transition {
+            func: Box::new(move |args| {
                 (t1.func)(args)?;
-                (t2.func)(args)?;
-                Ok(())
-            },
-            requires,
-            produces
-        ))
+                check_guards(&t2.guards, args)?;
+                (t2.func)(args)
+            }),
+            signature,
+            guards: t1.guards,
+            deferred_guards,
+            priority: t1.priority
+        })
     }
 }
 
@@ -250,17 +427,20 @@ where
         let mut t1 = self.into_transition_mut()?;
         let mut t2 = next.into_transition_mut()?;
 
-        let (requires,produces) = combine_requirements(t1.requires,t1.produces,t2.requires,t2.produces)?;
+        let signature = combine_signatures(t1.signature, t2.signature, !t2.guards.is_empty())?;
+        let deferred_guards = t1.deferred_guards || t2.deferred_guards || !t2.guards.is_empty();
 
-        Ok(TransitionMut::new(
-            move |args| {
+        Ok(TransitionMut {
+            func: Box::new(move |args| {
                 (t1.func)(args)?;
-                (t2.func)(args)?;
-                Ok(())
-            },
-            requires,
-            produces
-        ))
+                check_guards(&t2.guards, args)?;
+                (t2.func)(args)
+            }),
+            signature,
+            guards: t1.guards,
+            deferred_guards,
+            priority: t1.priority
+        })
     }
 }
 
@@ -273,16 +453,19 @@ where
         let t1 = self.into_transition_once()?;
         let t2 = next.into_transition_once()?;
 
-        let (requires,produces) = combine_requirements(t1.requires,t1.produces,t2.requires,t2.produces)?;
+        let signature = combine_signatures(t1.signature, t2.signature, !t2.guards.is_empty())?;
+        let deferred_guards = t1.deferred_guards || t2.deferred_guards || !t2.guards.is_empty();
 
-        Ok(TransitionOnce::new(
-            move |args| {
+        Ok(TransitionOnce {
+            func: Box::new(move |args| {
                 (t1.func)(args)?;
-                (t2.func)(args)?;
-                Ok(())
-            },
-            requires,
-            produces
-        ))
+                check_guards(&t2.guards, args)?;
+                (t2.func)(args)
+            }),
+            signature,
+            guards: t1.guards,
+            deferred_guards,
+            priority: t1.priority
+        })
     }
 }
\ No newline at end of file
@@ -0,0 +1,275 @@
+use std::{fmt::Debug, future::Future, marker::PhantomData, pin::Pin, task::{Context, Poll}};
+
+use crate::{state::Reservation, State, Truth};
+
+use super::{InvalidTransitionError, Signature, TransitionError, UnknownInput};
+use super::function::{arg_params, Absent, Argument, Forbidden, Optional, Owned, Param, ParamList, Parameter, TransitionInput, TransitionOutput};
+
+/// Inserts the result of an async transition function into the state.
+type Finish<'a> = Box<dyn FnOnce(&mut State) -> Result<(), TransitionError> + Send + 'a>;
+type AsyncFuture<'a> = Pin<Box<dyn Future<Output = Finish<'a>> + Send + 'a>>;
+type AsyncTransitionFn<'a> = dyn FnOnce(&mut State) -> Result<AsyncFuture<'a>, TransitionError> + 'a;
+
+/// A transition input that can be handed to an async transition function.
+///
+/// Async transition functions can keep their inputs across await points, so they have to own them.
+/// It is implemented for `Truth`, `Option`, `Absent` and `Param` inputs and tuples of them, but not for borrowed truths.
+pub(crate) trait AsyncInput<M>: TransitionInput<M> {
+    fn take(fetch: Self::Fetch) -> Self::Item<'static>;
+}
+
+impl<T> AsyncInput<Owned> for T
+where
+    T: Truth + 'static
+{
+    fn take(fetch: Self::Fetch) -> Self::Item<'static> {
+        fetch.expect("A truth was handed to a transition function twice")
+    }
+}
+
+impl<T,M> AsyncInput<Optional<M>> for Option<T>
+where
+    T: AsyncInput<M>
+{
+    fn take(fetch: Self::Fetch) -> Self::Item<'static> {
+        fetch.map(T::take)
+    }
+}
+
+impl<T> AsyncInput<Forbidden> for Absent<T>
+where
+    T: Truth + 'static
+{
+    fn take(_: Self::Fetch) -> Self::Item<'static> {
+        Absent(PhantomData)
+    }
+}
+
+impl<P> AsyncInput<Parameter> for Param<P> {
+    fn take(_: Self::Fetch) -> Self::Item<'static> {}
+}
+
+macro_rules! impl_async_in {
+    ($($T:ident $M:ident),*) => {
+        impl<$($T,)* $($M,)*> AsyncInput<($($M,)*)> for ($($T,)*)
+        where
+            $($T: AsyncInput<$M>,)*
+        {
+            #[allow(non_snake_case, clippy::unused_unit)]
+            fn take(fetch: Self::Fetch) -> Self::Item<'static> {
+                let ($($T,)*) = fetch;
+                ($(<$T as AsyncInput<$M>>::take($T),)*)
+            }
+        }
+    }
+}
+
+for_each_prefix!(impl_async_in,
+    A1 M1, A2 M2, A3 M3, A4 M4, A5 M5, A6 M6, A7 M7, A8 M8, A9 M9, A10 M10, A11 M11, A12 M12,
+    A13 M13, A14 M14, A15 M15, A16 M16, A17 M17, A18 M18, A19 M19, A20 M20, A21 M21, A22 M22, A23 M23, A24 M24);
+
+pub(crate) trait AsyncTransitionFunction<In,M,Param>
+where
+    In: AsyncInput<M>
+{
+    type Result: TransitionOutput + Send;
+    type Future: Future<Output = Self::Result> + Send;
+    fn call(self, input: In::Item<'static>, params: Param) -> Self::Future;
+}
+
+macro_rules! impl_async_fns {
+    ($($A:ident $M:ident),*) => {
+        impl<$($A, $M,)* Fut, Fun> AsyncTransitionFunction<($($A,)*),($($M,)*),<arg_params!($($A $M),*) as ParamList>::Tuple> for Fun
+        where
+            $($A: AsyncInput<$M> + Argument<$M>,)*
+            arg_params!($($A $M),*): ParamList,
+            Fut: Future + Send,
+            Fut::Output: TransitionOutput + Send,
+            Fun: FnOnce($($A,)*) -> Fut,
+            Fun: FnOnce($(<$A as Argument<$M>>::Value<'static>,)*) -> Fut
+        {
+            type Result = Fut::Output;
+            type Future = Fut;
+
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            fn call(self, input: <($($A,)*) as TransitionInput<($($M,)*)>>::Item<'static>, params: <arg_params!($($A $M),*) as ParamList>::Tuple) -> Self::Future {
+                fn call_inner<Fut, $($A,)*>(fun: impl FnOnce($($A,)*) -> Fut, $($A: $A,)*) -> Fut {
+                    fun($($A,)*)
+                }
+
+                let ($($A,)*) = input;
+                let params = <arg_params!($($A $M),*)>::from_tuple(params);
+                $(
+                    let ($A, params) = <$A as Argument<$M>>::value($A, params);
+                )*
+                let () = params;
+                call_inner(self, $($A,)*)
+            }
+        }
+    };
+}
+
+for_each_prefix!(impl_async_fns,
+    A1 M1, A2 M2, A3 M3, A4 M4, A5 M5, A6 M6, A7 M7, A8 M8, A9 M9, A10 M10, A11 M11, A12 M12,
+    A13 M13, A14 M14, A15 M15, A16 M16, A17 M17, A18 M18, A19 M19, A20 M20, A21 M21, A22 M22, A23 M23, A24 M24);
+
+/// A transition whose function returns a future.
+///
+/// Async transitions are started with `StateMachine::run_async`, which takes their inputs out of the state
+/// and returns a `PendingTransition`. They can be created from `async fn`s and other functions returning a future
+/// using the `IntoAsyncTransition` and `IntoAsyncTransitionParameterized` traits.
+///
+/// Like `TransitionOnce`, an async transition can only be run once.
+pub struct AsyncTransition<'a> {
+    pub(crate) func: Box<AsyncTransitionFn<'a>>,
+    pub(crate) signature: Signature,
+    pub(crate) priority: i32
+}
+
+impl<'a> AsyncTransition<'a> {
+    /// Sets the priority of this transition.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Returns the priority of this transition.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns the truths this transition reads from and writes to the state.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Takes the inputs of this transition out of the state and starts its function.
+    pub(crate) fn start(self, state: &mut State, reservation: Reservation) -> Result<PendingTransition<'a>, TransitionError> {
+        let (future, taken) = state.transaction_changes(self.func)?;
+        state.keep_taken(&reservation, taken);
+        Ok(PendingTransition {
+            future,
+            signature: Some(self.signature),
+            reservation: Some(reservation)
+        })
+    }
+}
+
+impl<'a> Debug for AsyncTransition<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(format!("AsyncTransition({}->{})", self.signature.requires.len(), self.signature.produces.len()).as_str())
+    }
+}
+
+/// An async transition that was started by `StateMachine::run_async`.
+///
+/// The pending transition is a future that resolves once the transition function finished. The truths the transition touches
+/// stay reserved until the resulting `CompletedTransition` is passed to `StateMachine::complete` or dropped,
+/// so no other transition can take or insert them in the meantime. The future does not depend on any executor,
+/// and can be sent to another thread.
+///
+/// Dropping a pending transition cancels it. The truths it took out of the state are dropped as well.
+#[must_use = "the transition does nothing unless it is polled and completed"]
+pub struct PendingTransition<'a> {
+    future: AsyncFuture<'a>,
+    signature: Option<Signature>,
+    reservation: Option<Reservation>
+}
+
+impl<'a> Future for PendingTransition<'a> {
+    type Output = CompletedTransition<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.future.as_mut().poll(cx) {
+            Poll::Ready(finish) => Poll::Ready(CompletedTransition {
+                finish,
+                signature: self.signature.take().expect("A pending transition was polled after it completed"),
+                reservation: self.reservation.take().expect("A pending transition was polled after it completed")
+            }),
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+
+impl<'a> Debug for PendingTransition<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PendingTransition")
+    }
+}
+
+/// The result of an async transition, that still has to be inserted into the state with `StateMachine::complete`.
+#[must_use = "the result of the transition is dropped unless it is passed to `StateMachine::complete`"]
+pub struct CompletedTransition<'a> {
+    pub(crate) finish: Finish<'a>,
+    pub(crate) signature: Signature,
+    pub(crate) reservation: Reservation
+}
+
+impl<'a> Debug for CompletedTransition<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CompletedTransition")
+    }
+}
+
+/// A trait that allows an object to be converted into an `AsyncTransition` with some parameters.
+///
+/// This trait is implemented for:
+///
+/// - The `AsyncTransition` type.
+/// - `FnOnce` types that return a future, like `async fn`s, and take up to 24 arguments, of which up to 8 can be parameters.
+///
+/// The inputs are owned by the future, so they can be truths, `Option`s of them, `Absent` or tuples of those, but not borrowed truths.
+/// The output of the future is handled like the return value of other transition functions.
+/// The future and its output have to be `Send`, so that the `PendingTransition` can be polled on any thread.
+pub trait IntoAsyncTransitionParameterized<'a,In,Param> {
+    /// Converts this object into an `AsyncTransition` with the given parameters.
+    fn into_async_transition_with(self, params: Param) -> Result<AsyncTransition<'a>,InvalidTransitionError>;
+}
+
+/// A trait that allows an object to be converted into an `AsyncTransition`.
+///
+/// See `IntoAsyncTransitionParameterized` for the objects it is implemented for, and `StateMachine::run_async` for an example.
+pub trait IntoAsyncTransition<'a,In> {
+    fn into_async_transition(self) -> Result<AsyncTransition<'a>,InvalidTransitionError>;
+}
+
+impl<'a> IntoAsyncTransitionParameterized<'a, UnknownInput, ()> for AsyncTransition<'a>
+{
+    fn into_async_transition_with(self, _params: ()) -> Result<AsyncTransition<'a>,InvalidTransitionError> {
+        Ok(self)
+    }
+}
+
+#[allow(private_bounds)]
+impl<'a,In,M,Param,F> IntoAsyncTransitionParameterized<'a,(In,M),Param> for F
+where
+    In: AsyncInput<M>,
+    Param: 'a,
+    F: AsyncTransitionFunction<In,M,Param> + 'a,
+    F::Future: 'a,
+    F::Result: 'a
+{
+    fn into_async_transition_with(self, params: Param) -> Result<AsyncTransition<'a>,InvalidTransitionError> {
+        Ok(AsyncTransition {
+            func: Box::new(move |args| {
+                let fetch = In::fetch(args)?;
+                let future = self.call(In::take(fetch), params);
+                Ok(Box::pin(async move {
+                    let res = future.await;
+                    Box::new(move |args: &mut State| res.insert_into(args)) as Finish<'a>
+                }) as AsyncFuture<'a>)
+            }),
+            signature: Signature::of::<In,M,F::Result>()?,
+            priority: 0
+        })
+    }
+}
+
+impl<'a,In,F> IntoAsyncTransition<'a,In> for F
+where
+    F: IntoAsyncTransitionParameterized<'a, In, ()>
+{
+    fn into_async_transition(self) -> Result<AsyncTransition<'a>,InvalidTransitionError> {
+        self.into_async_transition_with(())
+    }
+}
@@ -0,0 +1,315 @@
+use std::marker::PhantomData;
+
+use crate::{Id, State, Truth};
+
+use super::TransitionError;
+use super::function::{Borrowed, BorrowedMut, InputMarker, Owned, TransitionInput};
+
+/// A transition input that stands for a single truth, so that it can be one of several alternatives.
+///
+/// It is implemented for `Truth` types and references to them.
+pub(crate) trait AlternativeInput<M>: TransitionInput<M> {
+    fn truth() -> Id;
+}
+
+impl<T: Truth + 'static> AlternativeInput<Owned> for T {
+    fn truth() -> Id {
+        Id::of::<T>()
+    }
+}
+
+impl<T: Truth + 'static> AlternativeInput<Borrowed> for &T {
+    fn truth() -> Id {
+        Id::of::<T>()
+    }
+}
+
+impl<T: Truth + 'static> AlternativeInput<BorrowedMut> for &mut T {
+    fn truth() -> Id {
+        Id::of::<T>()
+    }
+}
+
+/// Marker for inputs that take the first of several alternative truths.
+pub struct Choice<M>(PhantomData<M>);
+
+impl<M> InputMarker for Choice<M> {}
+
+/// Returns the index of the first alternative that is in the state.
+fn choose(state: &State, alternatives: Vec<Id>) -> Result<usize, TransitionError> {
+    alternatives.iter()
+        .position(|id| state.contains_key(id))
+        .ok_or(TransitionError::MissingAlternative(alternatives))
+}
+
+/// A transition input that takes one of two truths, whichever is in the state, preferring `A`.
+///
+/// A transition taking `Either<A, B>` can run if `A` or `B` is in the state. It is reported in
+/// `Signature::alternatives`, so that `StateMachine::can_run` and the analyses of a `TransitionDictionary` handle it.
+/// `A` and `B` can be truths, which are taken out of the state, or references to truths, which are borrowed.
+/// For more than two alternatives, see `OneOf`.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct CardPayment(u32);
+///
+/// #[derive(Truth,Debug)]
+/// struct InvoicePayment(u32);
+///
+/// #[derive(Truth,Debug)]
+/// struct Paid(u32);
+///
+/// fn settle(payment: Either<CardPayment, InvoicePayment>) -> Paid {
+///     match payment {
+///         Either::Left(card) => Paid(card.0),
+///         Either::Right(invoice) => Paid(invoice.0)
+///     }
+/// }
+///
+/// let mut state_machine = StateMachine::new();
+/// assert!(!state_machine.can_run(&settle).unwrap());
+///
+/// state_machine.set_truth(InvoicePayment(30));
+/// assert!(state_machine.can_run(&settle).unwrap());
+///
+/// state_machine.run(settle).unwrap();
+/// assert_eq!(state_machine.unset_truth::<Paid>().unwrap().0, 30);
+/// assert!(!state_machine.has_truth::<InvoicePayment>());
+/// ```
+pub enum Either<A,B> {
+    Left(A),
+    Right(B)
+}
+
+impl<A,B,MA,MB> TransitionInput<Choice<(MA,MB)>> for Either<A,B>
+where
+    A: AlternativeInput<MA>,
+    B: AlternativeInput<MB>
+{
+    type Fetch = Either<A::Fetch, B::Fetch>;
+    type Item<'f> = Either<A::Item<'f>, B::Item<'f>>;
+
+    fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+        match choose(state, vec![A::truth(), B::truth()])? {
+            0 => A::fetch(state).map(Either::Left),
+            _ => B::fetch(state).map(Either::Right)
+        }
+    }
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+        match fetch {
+            Either::Left(a) => Either::Left(A::item(a)),
+            Either::Right(b) => Either::Right(B::item(b))
+        }
+    }
+
+    fn restore(fetch: Self::Fetch, state: &mut State) {
+        match fetch {
+            Either::Left(a) => A::restore(a, state),
+            Either::Right(b) => B::restore(b, state)
+        }
+    }
+
+    fn collect_required<C,E>(_: &mut C) -> Result<(),E>
+    where
+        C: FnMut(Id) -> Result<(),E>
+    {
+        Ok(())
+    }
+
+    fn collect_borrowed<C>(collector: &mut C)
+    where
+        C: FnMut(Id)
+    {
+        A::collect_borrowed(collector);
+        B::collect_borrowed(collector);
+    }
+
+    fn collect_optional<C>(_: &mut C)
+    where
+        C: FnMut(Id)
+    {}
+
+    fn collect_forbidden<C>(_: &mut C)
+    where
+        C: FnMut(Id)
+    {}
+
+    fn collect_alternatives<C>(collector: &mut C)
+    where
+        C: FnMut(Vec<Id>)
+    {
+        collector(vec![A::truth(), B::truth()])
+    }
+
+    fn collect_mutated<C>(collector: &mut C)
+    where
+        C: FnMut(Id)
+    {
+        A::collect_mutated(collector);
+        B::collect_mutated(collector);
+    }
+}
+
+/// A tuple of types that can be the alternatives of a `OneOf` input.
+///
+/// It is implemented for tuples of 2 to 8 types.
+pub trait Alternatives {
+    /// A tuple with an `Option` of every alternative.
+    type Options;
+}
+
+/// A transition input that takes the first truth of the tuple `T` that is in the state.
+///
+/// A transition taking `OneOf<(A, B, C)>` can run if `A`, `B` or `C` is in the state. Like `Either`, it is reported in
+/// `Signature::alternatives`, and its alternatives can be truths or references to truths.
+/// Exactly one of the options returned by `into_options` is `Some`.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct Card();
+///
+/// #[derive(Truth,Debug)]
+/// struct Invoice();
+///
+/// #[derive(Truth,Debug)]
+/// struct Voucher();
+///
+/// fn pay(payment: OneOf<(Card, &Invoice, Voucher)>) {
+///     assert_eq!(payment.index(), 1);
+///     let (card, invoice, voucher) = payment.into_options();
+///     assert!(card.is_none() && invoice.is_some() && voucher.is_none());
+/// }
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Voucher());
+/// state_machine.set_truth(Invoice());
+///
+/// state_machine.run(pay).unwrap();
+/// assert!(state_machine.has_truth::<Invoice>());
+/// assert!(state_machine.has_truth::<Voucher>());
+///
+/// let transition = pay.into_transition().unwrap();
+/// assert_eq!(transition.signature().alternatives(), &[vec![TruthId::of::<Card>(), TruthId::of::<Invoice>(), TruthId::of::<Voucher>()]]);
+/// ```
+pub struct OneOf<T: Alternatives> {
+    index: usize,
+    options: T::Options
+}
+
+impl<T: Alternatives> OneOf<T> {
+    /// Returns the index of the alternative that was taken.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the options of the alternatives, only the one that was taken is `Some`.
+    pub fn options(&self) -> &T::Options {
+        &self.options
+    }
+
+    /// Returns the options of the alternatives, only the one that was taken is `Some`.
+    pub fn into_options(self) -> T::Options {
+        self.options
+    }
+}
+
+macro_rules! impl_one_of {
+    ($($T:ident $M:ident $i:tt),*) => {
+        impl<$($T,)*> Alternatives for ($($T,)*) {
+            type Options = ($(Option<$T>,)*);
+        }
+
+        impl<$($T,)* $($M,)*> TransitionInput<Choice<($($M,)*)>> for OneOf<($($T,)*)>
+        where
+            $($T: AlternativeInput<$M>,)*
+        {
+            type Fetch = (usize, ($(Option<<$T as TransitionInput<$M>>::Fetch>,)*));
+            type Item<'f> = OneOf<($(<$T as TransitionInput<$M>>::Item<'f>,)*)>;
+
+            fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+                let index = choose(state, vec![$(<$T as AlternativeInput<$M>>::truth(),)*])?;
+                Ok((index, ($(
+                    if index == $i { Some(<$T as TransitionInput<$M>>::fetch(state)?) } else { None },
+                )*)))
+            }
+
+            #[allow(non_snake_case)]
+            fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+                let (index, ($($T,)*)) = fetch;
+                OneOf {
+                    index: *index,
+                    options: ($($T.as_mut().map(<$T as TransitionInput<$M>>::item),)*)
+                }
+            }
+
+            #[allow(non_snake_case)]
+            fn restore(fetch: Self::Fetch, state: &mut State) {
+                let (_, ($($T,)*)) = fetch;
+                $(
+                    if let Some(fetch) = $T {
+                        <$T as TransitionInput<$M>>::restore(fetch, state);
+                    }
+                )*
+            }
+
+            fn collect_required<C,E>(_: &mut C) -> Result<(),E>
+            where
+                C: FnMut(Id) -> Result<(),E>
+            {
+                Ok(())
+            }
+
+            fn collect_borrowed<C>(collector: &mut C)
+            where
+                C: FnMut(Id)
+            {
+                $(
+                    <$T as TransitionInput<$M>>::collect_borrowed(collector);
+                )*
+            }
+
+            fn collect_optional<C>(_: &mut C)
+            where
+                C: FnMut(Id)
+            {}
+
+            fn collect_forbidden<C>(_: &mut C)
+            where
+                C: FnMut(Id)
+            {}
+
+            fn collect_alternatives<C>(collector: &mut C)
+            where
+                C: FnMut(Vec<Id>)
+            {
+                collector(vec![$(<$T as AlternativeInput<$M>>::truth(),)*])
+            }
+
+            fn collect_mutated<C>(collector: &mut C)
+            where
+                C: FnMut(Id)
+            {
+                $(
+                    <$T as TransitionInput<$M>>::collect_mutated(collector);
+                )*
+            }
+        }
+    };
+}
+
+impl_one_of!(A1 M1 0, A2 M2 1);
+impl_one_of!(A1 M1 0, A2 M2 1, A3 M3 2);
+impl_one_of!(A1 M1 0, A2 M2 1, A3 M3 2, A4 M4 3);
+impl_one_of!(A1 M1 0, A2 M2 1, A3 M3 2, A4 M4 3, A5 M5 4);
+impl_one_of!(A1 M1 0, A2 M2 1, A3 M3 2, A4 M4 3, A5 M5 4, A6 M6 5);
+impl_one_of!(A1 M1 0, A2 M2 1, A3 M3 2, A4 M4 3, A5 M5 4, A6 M6 5, A7 M7 6);
+impl_one_of!(A1 M1 0, A2 M2 1, A3 M3 2, A4 M4 3, A5 M5 4, A6 M6 5, A7 M7 6, A8 M8 7);
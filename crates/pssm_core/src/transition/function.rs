@@ -1,4 +1,4 @@
-use std::{collections::HashSet, ops::{Deref, DerefMut}};
+use std::{collections::HashSet, convert::Infallible, error::Error, marker::PhantomData, ops::{Deref, DerefMut}};
 
 use crate::{Id, State, Truth, transition::{TransitionError, InvalidTransitionError}};
 
@@ -21,110 +21,344 @@ impl<P> DerefMut for Param<P> {
     }
 }
 
-pub(crate) trait TransitionFunction<In,Param> {
+/// A transition input that requires a truth to be absent from the state.
+/// 
+/// A transition taking `Absent<T>` can only be run if the truth `T` is not in the state.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth,Debug)]
+/// struct LoggedIn();
+/// 
+/// fn log_in(_: Absent<LoggedIn>) -> LoggedIn {
+///     LoggedIn()
+/// }
+/// 
+/// let mut state_machine = StateMachine::new();
+/// 
+/// assert!(state_machine.can_run(&log_in).unwrap());
+/// state_machine.run(log_in).unwrap();
+/// 
+/// assert!(!state_machine.can_run(&log_in).unwrap());
+/// assert!(state_machine.run(log_in).is_err());
+/// ```
+pub struct Absent<T>(pub(crate) PhantomData<T>);
+
+/// Marker for inputs that are taken out of the state by value.
+pub struct Owned;
+
+/// Marker for inputs that are borrowed immutably from the state.
+pub struct Borrowed;
+
+/// Marker for inputs that are borrowed mutably from the state.
+pub struct BorrowedMut;
+
+/// Marker for optional inputs.
+/// 
+/// An optional input is all-or-nothing: if any truth of a compound input like `Option<(A, B)>` is missing,
+/// the input is `None` and none of its truths are taken out of the state.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth,Debug)]
+/// struct Card();
+/// 
+/// #[derive(Truth,Debug)]
+/// struct Pin();
+/// 
+/// fn withdraw(credentials: Option<(Card, Pin)>) {
+///     assert!(credentials.is_none());
+/// }
+/// 
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Card());
+/// 
+/// state_machine.run(withdraw).unwrap();
+/// assert!(state_machine.has_truth::<Card>());
+/// ```
+pub struct Optional<M>(PhantomData<M>);
+
+/// Marker for inputs that require a truth to be absent.
+pub struct Forbidden;
+
+/// Marker for `Param` arguments.
+pub struct Parameter;
+
+/// Marker implemented by the markers of transition inputs, to keep them apart from `Param` arguments.
+pub(crate) trait InputMarker {}
+
+impl InputMarker for Owned {}
+impl InputMarker for Borrowed {}
+impl InputMarker for BorrowedMut {}
+impl<M> InputMarker for Optional<M> {}
+impl InputMarker for Forbidden {}
+
+/// An argument of a transition function, either a transition input or a `Param`.
+/// 
+/// The parameters of a transition are handed to its function as a list of nested pairs, `(P1, (P2, ()))`.
+/// Every argument takes the parameters it needs from the front of the list, so `Param`s can appear anywhere
+/// in the arguments, and get the parameters in the order they are declared.
+pub(crate) trait Argument<M>: TransitionInput<M> {
+    /// The value that is passed to the transition function.
+    type Value<'f>;
+
+    /// The list of parameters this argument takes from, followed by the rest of the list.
+    type Params<Rest>;
+
+    fn value<Rest>(item: Self::Item<'_>, params: Self::Params<Rest>) -> (Self::Value<'_>, Rest);
+}
+
+impl<T,M> Argument<M> for T
+where 
+    T: TransitionInput<M>,
+    M: InputMarker
+{
+    type Value<'f> = T::Item<'f>;
+    type Params<Rest> = Rest;
+
+    fn value<Rest>(item: Self::Item<'_>, params: Rest) -> (Self::Value<'_>, Rest) {
+        (item, params)
+    }
+}
+
+impl<P> Argument<Parameter> for Param<P> {
+    type Value<'f> = Param<P>;
+    type Params<Rest> = (P, Rest);
+
+    fn value<Rest>(_: Self::Item<'_>, (param, rest): (P, Rest)) -> (Self::Value<'_>, Rest) {
+        (Param(param), rest)
+    }
+}
+
+/// A list of parameters as nested pairs, `(P1, (P2, ()))`, that can be built from a tuple of parameters, `(P1, P2)`.
+pub(crate) trait ParamList {
+    type Tuple;
+
+    fn from_tuple(tuple: Self::Tuple) -> Self;
+}
+
+macro_rules! param_list {
+    () => { () };
+    ($P:tt $(, $Ps:tt)*) => { ($P, param_list!($($Ps),*)) };
+}
+
+macro_rules! impl_param_list {
+    ($($P:ident),*) => {
+        impl<$($P,)*> ParamList for param_list!($($P),*) {
+            type Tuple = ($($P,)*);
+
+            #[allow(non_snake_case, clippy::unused_unit)]
+            fn from_tuple(tuple: Self::Tuple) -> Self {
+                let ($($P,)*) = tuple;
+                param_list!($($P),*)
+            }
+        }
+    };
+}
+
+for_each_prefix!(impl_param_list, P1, P2, P3, P4, P5, P6, P7, P8);
+
+/// The list of parameters taken by the given arguments.
+macro_rules! arg_params {
+    () => { () };
+    ($A:ident $M:ident $(, $As:ident $Ms:ident)*) => { <$A as Argument<$M>>::Params<arg_params!($($As $Ms),*)> };
+}
+
+pub(crate) use arg_params;
+
+pub(crate) trait TransitionFunction<In,M,Param>
+where 
+    In: TransitionInput<M>
+{
     type Result: TransitionOutput;
-    fn call(&self, input: In, params: Param) -> Self::Result;
+    fn call(&self, input: In::Item<'_>, params: Param) -> Self::Result;
 }
 
-pub(crate) trait TransitionFunctionMut<In,Param> {
+pub(crate) trait TransitionFunctionMut<In,M,Param>
+where 
+    In: TransitionInput<M>
+{
     type Result: TransitionOutput;
-    fn call(&mut self, input: In, params: Param) -> Self::Result;
+    fn call(&mut self, input: In::Item<'_>, params: Param) -> Self::Result;
 }
 
-pub(crate) trait TransitionFunctionOnce<In,Param> {
+pub(crate) trait TransitionFunctionOnce<In,M,Param>
+where 
+    In: TransitionInput<M>
+{
     type Result: TransitionOutput;
-    fn call(self, input: In, params: Param) -> Self::Result;
+    fn call(self, input: In::Item<'_>, params: Param) -> Self::Result;
 }
 
+// The functions are required to accept both the declared argument types, so that the inputs can be inferred,
+// and the values made from the items borrowed from the fetched truths for any lifetime, so that they can actually be called.
+// Calling through a helper function lets the compiler pick the second signature.
 macro_rules! impl_trans_fns {
-    (
-        // input params
-        ($($I:ident),*),
-        // parameter params
-        ($($P:ident),*)
-    ) => {
-        impl<$($I,)* $($P,)* Res, Fun> TransitionFunction<($($I,)*),($($P,)*)> for Fun
+    ($($A:ident $M:ident),*) => {
+        impl<$($A, $M,)* Res, Fun> TransitionFunction<($($A,)*),($($M,)*),<arg_params!($($A $M),*) as ParamList>::Tuple> for Fun
         where
-            $($I: TransitionInput,)*
+            $($A: Argument<$M>,)*
+            arg_params!($($A $M),*): ParamList,
             Res: TransitionOutput,
-            Fun: Fn($($I,)* $(Param<$P>,)*) -> Res
+            Fun: Fn($($A,)*) -> Res,
+            Fun: for<'f> Fn($(<$A as Argument<$M>>::Value<'f>,)*) -> Res
         {
             type Result = Res;
 
-            #[allow(non_snake_case)]
-            fn call(&self, input: ($($I,)*), params: ($($P,)*)) -> Self::Result {
-                let ($($I,)*) = input;
-                let ($($P,)*) = params;
-                self($($I,)* $(Param($P),)*)
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            fn call(&self, input: <($($A,)*) as TransitionInput<($($M,)*)>>::Item<'_>, params: <arg_params!($($A $M),*) as ParamList>::Tuple) -> Self::Result {
+                fn call_inner<Res, $($A,)*>(fun: impl Fn($($A,)*) -> Res, $($A: $A,)*) -> Res {
+                    fun($($A,)*)
+                }
+
+                let ($($A,)*) = input;
+                let params = <arg_params!($($A $M),*)>::from_tuple(params);
+                $(
+                    let ($A, params) = <$A as Argument<$M>>::value($A, params);
+                )*
+                let () = params;
+                call_inner(self, $($A,)*)
             }
         }
 
-        impl<$($I,)* $($P,)* Res, Fun> TransitionFunctionMut<($($I,)*),($($P,)*)> for Fun
+        impl<$($A, $M,)* Res, Fun> TransitionFunctionMut<($($A,)*),($($M,)*),<arg_params!($($A $M),*) as ParamList>::Tuple> for Fun
         where
-            $($I: TransitionInput,)*
+            $($A: Argument<$M>,)*
+            arg_params!($($A $M),*): ParamList,
             Res: TransitionOutput,
-            Fun: FnMut($($I,)* $(Param<$P>,)*) -> Res
+            Fun: FnMut($($A,)*) -> Res,
+            Fun: for<'f> FnMut($(<$A as Argument<$M>>::Value<'f>,)*) -> Res
         {
             type Result = Res;
 
-            #[allow(non_snake_case)]
-            fn call(&mut self, input: ($($I,)*), params: ($($P,)*)) -> Self::Result {
-                let ($($I,)*) = input;
-                let ($($P,)*) = params;
-                self($($I,)* $(Param($P),)*)
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            fn call(&mut self, input: <($($A,)*) as TransitionInput<($($M,)*)>>::Item<'_>, params: <arg_params!($($A $M),*) as ParamList>::Tuple) -> Self::Result {
+                fn call_inner<Res, $($A,)*>(mut fun: impl FnMut($($A,)*) -> Res, $($A: $A,)*) -> Res {
+                    fun($($A,)*)
+                }
+
+                let ($($A,)*) = input;
+                let params = <arg_params!($($A $M),*)>::from_tuple(params);
+                $(
+                    let ($A, params) = <$A as Argument<$M>>::value($A, params);
+                )*
+                let () = params;
+                call_inner(self, $($A,)*)
             }
         }
 
-        impl<$($I,)* $($P,)* Res, Fun> TransitionFunctionOnce<($($I,)*),($($P,)*)> for Fun
+        impl<$($A, $M,)* Res, Fun> TransitionFunctionOnce<($($A,)*),($($M,)*),<arg_params!($($A $M),*) as ParamList>::Tuple> for Fun
         where
-            $($I: TransitionInput,)*
+            $($A: Argument<$M>,)*
+            arg_params!($($A $M),*): ParamList,
             Res: TransitionOutput,
-            Fun: FnOnce($($I,)* $(Param<$P>,)*) -> Res
+            Fun: FnOnce($($A,)*) -> Res,
+            Fun: for<'f> FnOnce($(<$A as Argument<$M>>::Value<'f>,)*) -> Res
         {
             type Result = Res;
 
-            #[allow(non_snake_case)]
-            fn call(self, input: ($($I,)*), params: ($($P,)*)) -> Self::Result {
-                let ($($I,)*) = input;
-                let ($($P,)*) = params;
-                self($($I,)* $(Param($P),)*)
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            fn call(self, input: <($($A,)*) as TransitionInput<($($M,)*)>>::Item<'_>, params: <arg_params!($($A $M),*) as ParamList>::Tuple) -> Self::Result {
+                fn call_inner<Res, $($A,)*>(fun: impl FnOnce($($A,)*) -> Res, $($A: $A,)*) -> Res {
+                    fun($($A,)*)
+                }
+
+                let ($($A,)*) = input;
+                let params = <arg_params!($($A $M),*)>::from_tuple(params);
+                $(
+                    let ($A, params) = <$A as Argument<$M>>::value($A, params);
+                )*
+                let () = params;
+                call_inner(self, $($A,)*)
             }
         }
     };
 }
 
-impl_trans_fns!((),());
-impl_trans_fns!((I1), ());
-impl_trans_fns!((I1, I2), ());
-impl_trans_fns!((I1, I2, I3), ());
-impl_trans_fns!((I1, I2, I3, I4), ());
-impl_trans_fns!((), (P1));
-impl_trans_fns!((I1), (P1));
-impl_trans_fns!((I1, I2), (P1));
-impl_trans_fns!((I1, I2, I3), (P1));
-impl_trans_fns!((I1, I2, I3, I4), (P1));
-impl_trans_fns!((), (P1, P2));
-impl_trans_fns!((I1), (P1, P2));
-impl_trans_fns!((I1, I2), (P1, P2));
-impl_trans_fns!((I1, I2, I3), (P1, P2));
-impl_trans_fns!((I1, I2, I3, I4), (P1, P2));
+for_each_prefix!(impl_trans_fns,
+    A1 M1, A2 M2, A3 M3, A4 M4, A5 M5, A6 M6, A7 M7, A8 M8, A9 M9, A10 M10, A11 M11, A12 M12,
+    A13 M13, A14 M14, A15 M15, A16 M16, A17 M17, A18 M18, A19 M19, A20 M20, A21 M21, A22 M22, A23 M23, A24 M24);
+
+/// Takes a truth out of the state.
+pub(crate) fn take_truth<T: Truth + 'static>(state: &mut State) -> Result<Box<T>, TransitionError> {
+    state.remove(&Id::of::<T>())
+        .ok_or_else(|| TransitionError::MissingTruth(Id::of::<T>()))
+        .map(|val| val.downcast::<T>().expect("Invalid type stored for a truth in the state"))
+}
 
 /// A trait that represents a transition input.
 /// 
 /// A transition input is a piece of data that can be taken from the state when running a transition.
 /// 
+/// Running a transition first fetches all of its inputs from the state, then hands the fetched
+/// items to the transition function and finally restores the truths that were only borrowed.
+/// 
 /// It is implemented for:
 /// 
-/// - `Truth` types
-/// - `Option<Truth>` types
-/// - Tuples of up to 8 `TransitionInput` types
-pub(crate) trait TransitionInput: Sized {
-    fn try_take_from(state: &mut State) -> Result<Self, TransitionError>;
+/// - `Truth` types, which are taken out of the state
+/// - `&Truth` and `&mut Truth` types, which are borrowed and stay in the state
+/// - `Option<TransitionInput>` types, which take all of the truths of the input or none of them
+/// - `Absent<Truth>` types, which require the truth to be absent
+/// - `Keyed<K, Truth>` types and references to them, which take or borrow a keyed truth
+/// - `Tagged<Tag>` types, which take every truth marked with the tag
+/// - `Either<A, B>` and `OneOf<(A, B, ...)>` types, which take the first of several truths that is in the state
+/// - `Param<P>` types, which take no truths and are handed the parameters of the transition
+/// - Tuples of up to 24 `TransitionInput` types
+/// 
+/// The marker `M` only exists to keep the implementations for owned and borrowed truths apart.
+pub(crate) trait TransitionInput<M>: Sized {
+    /// The truths that are held outside of the state while the transition runs.
+    type Fetch;
+
+    /// The value that is passed to the transition function.
+    type Item<'f>;
+
+    fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError>;
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_>;
+
+    fn restore(fetch: Self::Fetch, state: &mut State);
 
     fn collect_required<C,E>(collector: &mut C) -> Result<(),E>
     where 
         C: FnMut(Id) -> Result<(),E>;
 
+    fn collect_borrowed<C>(collector: &mut C)
+    where 
+        C: FnMut(Id);
+
+    fn collect_optional<C>(collector: &mut C)
+    where 
+        C: FnMut(Id);
+
+    fn collect_forbidden<C>(collector: &mut C)
+    where 
+        C: FnMut(Id);
+
+    /// Collects the tags of the truths this input takes out of the state, whatever their type.
+    fn collect_drained<C>(_: &mut C)
+    where 
+        C: FnMut(&'static str)
+    {}
+
+    /// Collects the lists of truths of which this input takes the first one that is in the state.
+    fn collect_alternatives<C>(_: &mut C)
+    where 
+        C: FnMut(Vec<Id>)
+    {}
+
+    /// Collects the borrowed truths this input can change in place.
+    fn collect_mutated<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
     fn required() -> Result<HashSet<Id>,InvalidTransitionError> {
         let mut ids = HashSet::new();
         Self::collect_required(&mut |id| { 
@@ -135,54 +369,395 @@ pub(crate) trait TransitionInput: Sized {
             }
         }).map(|_| ids)
     }
+
+    fn borrowed() -> HashSet<Id> {
+        let mut ids = HashSet::new();
+        Self::collect_borrowed(&mut |id| { ids.insert(id); });
+        ids
+    }
+
+    fn optional() -> HashSet<Id> {
+        let mut ids = HashSet::new();
+        Self::collect_optional(&mut |id| { ids.insert(id); });
+        ids
+    }
+
+    fn forbidden() -> HashSet<Id> {
+        let mut ids = HashSet::new();
+        Self::collect_forbidden(&mut |id| { ids.insert(id); });
+        ids
+    }
+
+    fn drained() -> HashSet<&'static str> {
+        let mut tags = HashSet::new();
+        Self::collect_drained(&mut |tag| { tags.insert(tag); });
+        tags
+    }
+
+    fn alternatives() -> Vec<Vec<Id>> {
+        let mut alternatives = Vec::new();
+        Self::collect_alternatives(&mut |ids| alternatives.push(ids));
+        alternatives
+    }
+
+    fn mutated() -> HashSet<Id> {
+        let mut ids = HashSet::new();
+        Self::collect_mutated(&mut |id| { ids.insert(id); });
+        ids
+    }
+}
+
+impl<T> TransitionInput<Owned> for T 
+where 
+    T: Truth + 'static
+{
+    type Fetch = Option<T>;
+    type Item<'f> = T;
+
+    fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+        take_truth::<T>(state).map(|val| Some(*val))
+    }
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+        fetch.take().expect("A truth was handed to a transition function twice")
+    }
+
+    fn restore(fetch: Self::Fetch, state: &mut State) {
+        if let Some(val) = fetch {
+            state.put_back(Id::of::<T>(), Box::new(val));
+        }
+    }
+
+    fn collect_required<C,E>(collector: &mut C) -> Result<(),E>
+    where 
+        C: FnMut(Id) -> Result<(),E>
+    {
+        collector(Id::of::<T>())
+    }
+
+    fn collect_borrowed<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_optional<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_forbidden<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+}
+
+impl<T> TransitionInput<Borrowed> for &T 
+where 
+    T: Truth + 'static
+{
+    type Fetch = Box<T>;
+    type Item<'f> = &'f T;
+
+    fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+        take_truth::<T>(state)
+    }
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+        fetch
+    }
+
+    fn restore(fetch: Self::Fetch, state: &mut State) {
+        state.put_back(Id::of::<T>(), fetch);
+    }
+
+    fn collect_required<C,E>(collector: &mut C) -> Result<(),E>
+    where 
+        C: FnMut(Id) -> Result<(),E>
+    {
+        collector(Id::of::<T>())
+    }
+
+    fn collect_borrowed<C>(collector: &mut C)
+    where 
+        C: FnMut(Id)
+    {
+        collector(Id::of::<T>())
+    }
+
+    fn collect_optional<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_forbidden<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
 }
 
-impl<T> TransitionInput for T 
+impl<T> TransitionInput<BorrowedMut> for &mut T 
 where 
     T: Truth + 'static
 {
-    fn try_take_from(state: &mut State) -> Result<Self, TransitionError> {
-        state.remove(&T::id())
-            .ok_or_else(|| TransitionError::MissingTruth(T::id()))
-            .map(|val| *val.downcast::<T>().expect("Invalid type stored for a truth in the state"))
+    type Fetch = Box<T>;
+    type Item<'f> = &'f mut T;
+
+    fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+        take_truth::<T>(state)
+    }
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+        fetch
+    }
+
+    fn restore(fetch: Self::Fetch, state: &mut State) {
+        state.put_back(Id::of::<T>(), fetch);
     }
 
     fn collect_required<C,E>(collector: &mut C) -> Result<(),E>
     where 
         C: FnMut(Id) -> Result<(),E>
     {
-        collector(T::id())
+        collector(Id::of::<T>())
+    }
+
+    fn collect_borrowed<C>(collector: &mut C)
+    where 
+        C: FnMut(Id)
+    {
+        collector(Id::of::<T>())
+    }
+
+    fn collect_optional<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_forbidden<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_mutated<C>(collector: &mut C)
+    where 
+        C: FnMut(Id)
+    {
+        collector(Id::of::<T>())
     }
 }
 
-impl<T> TransitionInput for Option<T> 
+impl<T,M> TransitionInput<Optional<M>> for Option<T> 
 where 
-    T: TransitionInput + 'static
+    T: TransitionInput<M>
 {
-    fn try_take_from(state: &mut State) -> Result<Self, TransitionError> {
-        match T::try_take_from(state) {
+    type Fetch = Option<T::Fetch>;
+    type Item<'f> = Option<T::Item<'f>>;
+
+    fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+        let mut present = T::collect_required(&mut |id| if state.contains_key(&id) { Ok(()) } else { Err(()) }).is_ok();
+        T::collect_alternatives(&mut |ids| present &= ids.iter().any(|id| state.contains_key(id)));
+        T::collect_forbidden(&mut |id| present &= !state.contains_key(&id));
+        if !present {
+            return Ok(None);
+        }
+
+        match T::fetch(state) {
             Ok(val) => Ok(Some(val)),
-            Err(TransitionError::MissingTruth(_)) => Ok(None)
+            Err(TransitionError::MissingTruth(_) | TransitionError::MissingAlternative(_) | TransitionError::ForbiddenTruth(_)) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+        fetch.as_mut().map(T::item)
+    }
+
+    fn restore(fetch: Self::Fetch, state: &mut State) {
+        if let Some(val) = fetch {
+            T::restore(val, state);
+        }
+    }
+
+    fn collect_required<C,E>(_: &mut C) -> Result<(),E>
+    where 
+        C: FnMut(Id) -> Result<(),E>
+    {
+        Ok(())
+    }
+
+    fn collect_borrowed<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_optional<C>(collector: &mut C)
+    where 
+        C: FnMut(Id)
+    {
+        let borrowed = T::borrowed();
+        let _ = T::collect_required(&mut |id| {
+            if !borrowed.contains(&id) {
+                collector(id);
+            }
+            Ok::<(),()>(())
+        });
+        T::collect_alternatives(&mut |ids| {
+            for id in ids {
+                if !borrowed.contains(&id) {
+                    collector(id);
+                }
+            }
+        });
+        T::collect_optional(collector);
+    }
+
+    fn collect_forbidden<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_drained<C>(collector: &mut C)
+    where 
+        C: FnMut(&'static str)
+    {
+        T::collect_drained(collector);
+    }
+
+    fn collect_mutated<C>(collector: &mut C)
+    where 
+        C: FnMut(Id)
+    {
+        T::collect_mutated(collector);
+    }
+}
+
+impl<T> TransitionInput<Forbidden> for Absent<T> 
+where 
+    T: Truth + 'static
+{
+    type Fetch = ();
+    type Item<'f> = Absent<T>;
+
+    fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+        if state.contains_key(&Id::of::<T>()) {
+            Err(TransitionError::ForbiddenTruth(Id::of::<T>()))
+        } else {
+            Ok(())
         }
     }
 
+    fn item(_: &mut Self::Fetch) -> Self::Item<'_> {
+        Absent(PhantomData)
+    }
+
+    fn restore(_: Self::Fetch, _: &mut State) {}
+
+    fn collect_required<C,E>(_: &mut C) -> Result<(),E>
+    where 
+        C: FnMut(Id) -> Result<(),E>
+    {
+        Ok(())
+    }
+
+    fn collect_borrowed<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_optional<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_forbidden<C>(collector: &mut C)
+    where 
+        C: FnMut(Id)
+    {
+        collector(Id::of::<T>())
+    }
+}
+
+// Parameters are not taken from the state, they are handed to the function by `Argument::value`.
+impl<P> TransitionInput<Parameter> for Param<P> {
+    type Fetch = ();
+    type Item<'f> = ();
+
+    fn fetch(_: &mut State) -> Result<Self::Fetch, TransitionError> {
+        Ok(())
+    }
+
+    fn item(_: &mut Self::Fetch) -> Self::Item<'_> {}
+
+    fn restore(_: Self::Fetch, _: &mut State) {}
+
     fn collect_required<C,E>(_: &mut C) -> Result<(),E>
     where 
         C: FnMut(Id) -> Result<(),E>
     {
         Ok(())
     }
+
+    fn collect_borrowed<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_optional<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    fn collect_forbidden<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
 }
 
 macro_rules! impl_trans_in {
-    ($($T:ident),*) => {
-        impl<$($T,)*> TransitionInput for ($($T,)*) 
+    ($($T:ident $M:ident),*) => {
+        impl<$($M,)*> InputMarker for ($($M,)*) {}
+
+        impl<$($T,)* $($M,)*> TransitionInput<($($M,)*)> for ($($T,)*) 
         where 
-            $($T: TransitionInput,)*
+            $($T: TransitionInput<$M>,)*
         {
-            #[allow(unused)]
-            fn try_take_from(state: &mut State) -> Result<Self, TransitionError> {
-                Ok(($(<$T>::try_take_from(state)?,)*))
+            type Fetch = ($(<$T as TransitionInput<$M>>::Fetch,)*);
+            type Item<'f> = ($(<$T as TransitionInput<$M>>::Item<'f>,)*);
+
+            // If an input can not be fetched, the inputs fetched before it are restored.
+            #[allow(non_snake_case, unused, clippy::unused_unit)]
+            fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+                let mut failed = None;
+                let ($($T,)*) = ($(
+                    match failed {
+                        Some(_) => None,
+                        None => <$T as TransitionInput<$M>>::fetch(state).map_err(|e| failed = Some(e)).ok()
+                    },
+                )*);
+
+                if let Some(e) = failed {
+                    $(
+                        if let Some(fetch) = $T {
+                            <$T as TransitionInput<$M>>::restore(fetch, state);
+                        }
+                    )*
+                    return Err(e);
+                }
+                Ok(($($T.expect("Every input was fetched"),)*))
+            }
+
+            #[allow(non_snake_case, clippy::unused_unit)]
+            fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+                let ($($T,)*) = fetch;
+                ($(<$T as TransitionInput<$M>>::item($T),)*)
+            }
+
+            #[allow(non_snake_case, unused)]
+            fn restore(fetch: Self::Fetch, state: &mut State) {
+                let ($($T,)*) = fetch;
+                $(
+                    <$T as TransitionInput<$M>>::restore($T, state);
+                )*
             }
 
             #[allow(unused)]
@@ -191,23 +766,77 @@ macro_rules! impl_trans_in {
                 C: FnMut(Id) -> Result<(),E>
             {
                 $(
-                    <$T>::collect_required(collector)?;
+                    <$T as TransitionInput<$M>>::collect_required(collector)?;
                 )*
                 Ok(())
             }
+
+            #[allow(unused)]
+            fn collect_borrowed<C>(collector: &mut C)
+            where 
+                C: FnMut(Id)
+            {
+                $(
+                    <$T as TransitionInput<$M>>::collect_borrowed(collector);
+                )*
+            }
+
+            #[allow(unused)]
+            fn collect_optional<C>(collector: &mut C)
+            where 
+                C: FnMut(Id)
+            {
+                $(
+                    <$T as TransitionInput<$M>>::collect_optional(collector);
+                )*
+            }
+
+            #[allow(unused)]
+            fn collect_forbidden<C>(collector: &mut C)
+            where 
+                C: FnMut(Id)
+            {
+                $(
+                    <$T as TransitionInput<$M>>::collect_forbidden(collector);
+                )*
+            }
+
+            #[allow(unused)]
+            fn collect_drained<C>(collector: &mut C)
+            where 
+                C: FnMut(&'static str)
+            {
+                $(
+                    <$T as TransitionInput<$M>>::collect_drained(collector);
+                )*
+            }
+
+            #[allow(unused)]
+            fn collect_alternatives<C>(collector: &mut C)
+            where 
+                C: FnMut(Vec<Id>)
+            {
+                $(
+                    <$T as TransitionInput<$M>>::collect_alternatives(collector);
+                )*
+            }
+
+            #[allow(unused)]
+            fn collect_mutated<C>(collector: &mut C)
+            where 
+                C: FnMut(Id)
+            {
+                $(
+                    <$T as TransitionInput<$M>>::collect_mutated(collector);
+                )*
+            }
         }
     }
 }
 
-impl_trans_in!();
-impl_trans_in!(A1);
-impl_trans_in!(A1, A2);
-impl_trans_in!(A1, A2, A3);
-impl_trans_in!(A1, A2, A3, A4);
-impl_trans_in!(A1, A2, A3, A4, A5);
-impl_trans_in!(A1, A2, A3, A4, A5, A6);
-impl_trans_in!(A1, A2, A3, A4, A5, A6, A7);
-impl_trans_in!(A1, A2, A3, A4, A5, A6, A7, A8);
+for_each_prefix!(impl_trans_in,
+    A1 M1, A2 M2, A3 M3, A4 M4, A5 M5, A6 M6, A7 M7, A8 M8, A9 M9, A10 M10, A11 M11, A12 M12,
+    A13 M13, A14 M14, A15 M15, A16 M16, A17 M17, A18 M18, A19 M19, A20 M20, A21 M21, A22 M22, A23 M23, A24 M24);
 
 /// A trait that represents a transition result.
 /// 
@@ -217,15 +846,33 @@ impl_trans_in!(A1, A2, A3, A4, A5, A6, A7, A8);
 /// 
 /// - `Truth` types
 /// - `Option<Truth>` types
-/// - Tuples of up to 8 `TransitionOutput` types
+/// - `Result<TransitionOutput, E>` types, where the error fails the transition
+/// - Tuples of up to 16 `TransitionOutput` types
 pub(crate) trait TransitionOutput {
 
-    fn insert_into(self, state: &mut State);
+    fn insert_into(self, state: &mut State) -> Result<(), TransitionError>;
 
     fn collect_produces<C,E>(collector: C) -> Result<(),E>
     where 
         C: FnMut(Id) -> Result<(),E>;
 
+    /// Collects the truths that are only inserted into the state for some of the values the transition function returns.
+    fn collect_may_produce<C>(_: &mut C)
+    where 
+        C: FnMut(Id)
+    {}
+
+    /// Whether some of the values the transition function returns fail the transition.
+    fn fallible() -> bool {
+        false
+    }
+
+    fn may_produce() -> HashSet<Id> {
+        let mut ids = HashSet::new();
+        Self::collect_may_produce(&mut |id| { ids.insert(id); });
+        ids
+    }
+
     fn produces() -> Result<HashSet<Id>,InvalidTransitionError> {
         let mut ids = HashSet::new();
         Self::collect_produces(|id| {
@@ -239,15 +886,16 @@ pub(crate) trait TransitionOutput {
 }
 
 impl<T: Truth + 'static> TransitionOutput for T {
-    fn insert_into(self, state: &mut State) {
-        state.insert(T::id(), Box::new(self));
+    fn insert_into(self, state: &mut State) -> Result<(), TransitionError> {
+        state.insert(Id::of::<T>(), Box::new(self));
+        Ok(())
     }
 
     fn collect_produces<C,E>(mut collector: C) -> Result<(),E>
     where 
         C: FnMut(Id) -> Result<(),E>
     {
-        collector(T::id())
+        collector(Id::of::<T>())
     }
 }
 
@@ -255,9 +903,10 @@ impl<A> TransitionOutput for Option<A>
 where 
     A: TransitionOutput
 {
-    fn insert_into(self, state: &mut State) {
-        if let Some(a) = self {
-            a.insert_into(state);
+    fn insert_into(self, state: &mut State) -> Result<(), TransitionError> {
+        match self {
+            Some(a) => a.insert_into(state),
+            None => Ok(())
         }
     }
 
@@ -267,6 +916,52 @@ where
     {
         Ok(())
     }
+
+    fn collect_may_produce<C>(collector: &mut C)
+    where 
+        C: FnMut(Id)
+    {
+        let _ = A::collect_produces(|id| {
+            collector(id);
+            Ok::<(),Infallible>(())
+        });
+        A::collect_may_produce(collector);
+    }
+
+    fn fallible() -> bool {
+        A::fallible()
+    }
+}
+
+impl<A,E> TransitionOutput for Result<A,E>
+where 
+    A: TransitionOutput,
+    E: Into<Box<dyn Error + Send + Sync>>
+{
+    fn insert_into(self, state: &mut State) -> Result<(), TransitionError> {
+        match self {
+            Ok(a) => a.insert_into(state),
+            Err(e) => Err(TransitionError::Failed(e.into()))
+        }
+    }
+
+    fn collect_produces<C,Err>(collector: C) -> Result<(),Err>
+    where 
+        C: FnMut(Id) -> Result<(),Err>
+    {
+        A::collect_produces(collector)
+    }
+
+    fn collect_may_produce<C>(collector: &mut C)
+    where 
+        C: FnMut(Id)
+    {
+        A::collect_may_produce(collector)
+    }
+
+    fn fallible() -> bool {
+        true
+    }
 }
 
 macro_rules! impl_trans_out {
@@ -276,11 +971,12 @@ macro_rules! impl_trans_out {
             $($T: TransitionOutput,)*
         {
             #[allow(non_snake_case, unused)]
-            fn insert_into(self, state: &mut State) {
+            fn insert_into(self, state: &mut State) -> Result<(), TransitionError> {
                 let ($($T,)*) = self;
                 $(
-                    $T.insert_into(state);
+                    $T.insert_into(state)?;
                 )*
+                Ok(())
             }
 
             #[allow(unused)]
@@ -293,16 +989,22 @@ macro_rules! impl_trans_out {
                 )*
                 Ok(())
             }
+
+            #[allow(unused)]
+            fn collect_may_produce<C>(collector: &mut C)
+            where 
+                C: FnMut(Id)
+            {
+                $(
+                    $T::collect_may_produce(collector);
+                )*
+            }
+
+            fn fallible() -> bool {
+                false $(|| $T::fallible())*
+            }
         }
     }
 }
 
-impl_trans_out!();
-impl_trans_out!(A1);
-impl_trans_out!(A1, A2);
-impl_trans_out!(A1, A2, A3);
-impl_trans_out!(A1, A2, A3, A4);
-impl_trans_out!(A1, A2, A3, A4, A5);
-impl_trans_out!(A1, A2, A3, A4, A5, A6);
-impl_trans_out!(A1, A2, A3, A4, A5, A6, A7);
-impl_trans_out!(A1, A2, A3, A4, A5, A6, A7, A8);
\ No newline at end of file
+for_each_prefix!(impl_trans_out, A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11, A12, A13, A14, A15, A16);
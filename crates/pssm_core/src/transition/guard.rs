@@ -0,0 +1,140 @@
+use crate::StateView;
+
+use super::{IntoTransition, IntoTransitionMut, IntoTransitionOnce, InvalidTransitionError, Transition, TransitionMut, TransitionOnce};
+
+/// A trait for attaching a guard to a transition.
+///
+/// A guard is a predicate on a read-only view of the state. The transition can only be run if the guard accepts the state,
+/// in addition to the required truths being present. Guards are evaluated by `StateMachine::can_run_transition` and
+/// `TransitionDictionary::runnable_transitions` without consuming any truths.
+///
+/// Multiple guards can be attached to the same transition, all of them have to accept the state.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct Balance(i32);
+///
+/// fn withdraw(balance: &mut Balance) {
+///     balance.0 -= 10;
+/// }
+///
+/// let transition = withdraw.guard(|state| state.get::<Balance>().is_some_and(|b| b.0 >= 10)).unwrap();
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Balance(15));
+///
+/// assert!(state_machine.can_run_transition(&transition));
+/// state_machine.run_ref_unchecked(&transition);
+/// assert!(!state_machine.can_run_transition(&transition));
+///
+/// let error = state_machine.run(transition).unwrap_err();
+/// assert!(matches!(error, TransitionCallError::TransitionError(TransitionError::GuardRejected)));
+/// assert_eq!(state_machine.unset_truth::<Balance>().unwrap().0, 5);
+/// ```
+pub trait Guard<'a,In> {
+    /// Converts this object into a `Transition` that can only be run if the given guard accepts the state.
+    fn guard<G>(self, guard: G) -> Result<Transition<'a>,InvalidTransitionError>
+    where
+        G: Fn(&StateView) -> bool + 'a;
+}
+
+/// A trait for attaching a guard to a mutable transition.
+///
+/// See `Guard` for more details.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct Ready();
+///
+/// let mut count = 0;
+/// let count_up = |_: &Ready| count += 1;
+///
+/// let transition = count_up.guard_mut(|state| !state.has::<Ready>()).unwrap();
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Ready());
+///
+/// assert!(!state_machine.can_run_transition_mut(&transition));
+/// ```
+pub trait GuardMut<'a,In> {
+    /// Converts this object into a `TransitionMut` that can only be run if the given guard accepts the state.
+    fn guard_mut<G>(self, guard: G) -> Result<TransitionMut<'a>,InvalidTransitionError>
+    where
+        G: Fn(&StateView) -> bool + 'a;
+}
+
+/// A trait for attaching a guard to a transition that can only be run once.
+///
+/// See `Guard` for more details.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct A(i32);
+///
+/// fn consume_a(a: A) {
+///     println!("{:?}", a);
+/// }
+///
+/// let transition = consume_a.guard_once(|state| state.get::<A>().is_some_and(|a| a.0 > 0)).unwrap();
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(A(0));
+///
+/// assert!(!state_machine.can_run_transition_once(&transition));
+/// assert!(state_machine.run(transition).is_err());
+/// assert!(state_machine.has_truth::<A>());
+/// ```
+pub trait GuardOnce<'a,In> {
+    /// Converts this object into a `TransitionOnce` that can only be run if the given guard accepts the state.
+    fn guard_once<G>(self, guard: G) -> Result<TransitionOnce<'a>,InvalidTransitionError>
+    where
+        G: Fn(&StateView) -> bool + 'a;
+}
+
+impl<'a,I,In> Guard<'a,In> for I
+where
+    I: IntoTransition<'a,In>
+{
+    fn guard<G>(self, guard: G) -> Result<Transition<'a>,InvalidTransitionError>
+    where G: Fn(&StateView) -> bool + 'a {
+        let mut transition = self.into_transition()?;
+        transition.guards.push(Box::new(guard));
+        Ok(transition)
+    }
+}
+
+impl<'a,I,In> GuardMut<'a,In> for I
+where
+    I: IntoTransitionMut<'a,In>
+{
+    fn guard_mut<G>(self, guard: G) -> Result<TransitionMut<'a>,InvalidTransitionError>
+    where G: Fn(&StateView) -> bool + 'a {
+        let mut transition = self.into_transition_mut()?;
+        transition.guards.push(Box::new(guard));
+        Ok(transition)
+    }
+}
+
+impl<'a,I,In> GuardOnce<'a,In> for I
+where
+    I: IntoTransitionOnce<'a,In>
+{
+    fn guard_once<G>(self, guard: G) -> Result<TransitionOnce<'a>,InvalidTransitionError>
+    where G: Fn(&StateView) -> bool + 'a {
+        let mut transition = self.into_transition_once()?;
+        transition.guards.push(Box::new(guard));
+        Ok(transition)
+    }
+}
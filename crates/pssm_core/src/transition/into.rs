@@ -1,6 +1,6 @@
 use crate::transition::InvalidTransitionError;
 
-use super::{Transition, TransitionMut, TransitionOnce};
+use super::{Signature, Transition, TransitionMut, TransitionOnce};
 use super::function::{TransitionFunction, TransitionFunctionMut, TransitionFunctionOnce, TransitionInput, TransitionOutput};
 
 /// A marker type for transitions that take an unknown input. 
@@ -11,14 +11,37 @@ pub struct UnknownInput();
 /// This trait is implemented for:
 /// 
 /// - The `Transition` type.
-/// - `Fn` types that take up to 4 inputs and up to 2 parameters
+/// - `Fn` types that take up to 24 arguments, of which up to 8 can be parameters
 /// 
 /// # Parameters
 /// 
-/// Function parameters must be ordered as inputs first, then parameters.
-/// - Inputs must implement `TransitionInput`
-/// - Parameters must be of type `Param<P>`.
-/// - Return type must implement `TransitionOutput`
+/// Inputs and parameters can be declared in any order.
+/// - Inputs must implement `TransitionInput`, borrowed inputs are written as `&T` or `&mut T`
+/// - Parameters must be of type `Param<P>`, they are given as a tuple in the order they are declared.
+/// - Return type must implement `TransitionOutput`, a `Result<Output, E>` makes the transition fallible
+/// 
+/// # Examples
+/// 
+/// ```
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth,Debug)]
+/// struct Balance(i32);
+/// 
+/// #[derive(Truth,Debug)]
+/// struct Fee(i32);
+/// 
+/// fn transfer(Param(amount): Param<i32>, balance: &mut Balance, fee: &Fee, Param(times): Param<i32>) {
+///     balance.0 -= (amount + fee.0) * times;
+/// }
+/// 
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Balance(100));
+/// state_machine.set_truth(Fee(1));
+/// 
+/// state_machine.run(transfer.into_transition_with((10, 2)).unwrap()).unwrap();
+/// assert_eq!(state_machine.unset_truth::<Balance>().unwrap().0, 78);
+/// ```
 pub trait IntoTransitionParameterized<'a,In,Param> {
     /// Converts this object into a `Transition` with the given parameters.
     /// 
@@ -35,14 +58,14 @@ pub trait IntoTransitionParameterized<'a,In,Param> {
 /// 
 /// - The `TransitionMut` type.
 /// - The `Transition` type.
-/// - `FnMut` types that take up to 4 inputs and up to 2 parameters.
+/// - `FnMut` types that take up to 24 arguments, of which up to 8 can be parameters.
 /// 
 /// # Parameters
 /// 
-/// Function parameters must be ordered as inputs first, then parameters.
-/// - Inputs must implement `TransitionInput`
-/// - Parameters must be of type `Param<P>`.
-/// - Return type must implement `TransitionOutput`
+/// Inputs and parameters can be declared in any order.
+/// - Inputs must implement `TransitionInput`, borrowed inputs are written as `&T` or `&mut T`
+/// - Parameters must be of type `Param<P>`, they are given as a tuple in the order they are declared.
+/// - Return type must implement `TransitionOutput`, a `Result<Output, E>` makes the transition fallible
 pub trait IntoTransitionMutParameterized<'a,In,Param> {
     /// Converts this object into a `TransitionMut` with the given parameters.
     /// 
@@ -60,14 +83,14 @@ pub trait IntoTransitionMutParameterized<'a,In,Param> {
 /// - The `TransitionOnce` type.
 /// - The `TransitionMut` type.
 /// - The `Transition` type.
-/// - `FnOnce` types that take up to 4 inputs and up to 2 parameters.
+/// - `FnOnce` types that take up to 24 arguments, of which up to 8 can be parameters.
 /// 
 /// # Parameters
 /// 
-/// Function parameters must be ordered as inputs first, then parameters.
-/// - Inputs must implement `TransitionInput`
-/// - Parameters must be of type `Param<P>`.
-/// - Return type must implement `TransitionOutput`
+/// Inputs and parameters can be declared in any order.
+/// - Inputs must implement `TransitionInput`, borrowed inputs are written as `&T` or `&mut T`
+/// - Parameters must be of type `Param<P>`, they are given as a tuple in the order they are declared.
+/// - Return type must implement `TransitionOutput`, a `Result<Output, E>` makes the transition fallible
 pub trait IntoTransitionOnceParameterized<'a,In,Param> {
     /// Converts this object into a `TransitionOnce` with the given parameters.
     /// 
@@ -85,22 +108,21 @@ impl<'a> IntoTransitionParameterized<'a, UnknownInput, ()> for Transition<'a>
     }
 }
 
-impl<'a,In,Param,F> IntoTransitionParameterized<'a,In,Param> for F
+impl<'a,In,M,Param,F> IntoTransitionParameterized<'a,(In,M),Param> for F
 where 
-    In: TransitionInput,
+    In: TransitionInput<M>,
     Param: Clone + 'a,
-    F: TransitionFunction<In,Param> + 'a
+    F: TransitionFunction<In,M,Param> + 'a
 {
     fn into_transition_with(self, params: Param) -> Result<Transition<'a>,InvalidTransitionError> {
         Ok(Transition::new(
             move |args| {
-                let input = <In>::try_take_from(args)?;
-                let res = self.call(input, params.clone());
-                res.insert_into(args);
-                Ok(())
+                let mut fetch = In::fetch(args)?;
+                let res = self.call(In::item(&mut fetch), params.clone());
+                In::restore(fetch, args);
+                res.insert_into(args)
             },
-            <In>::required()?,
-            F::Result::produces()?
+            Signature::of::<In,M,F::Result>()?
         ))
     }
 }
@@ -115,30 +137,31 @@ impl<'a> IntoTransitionMutParameterized<'a, UnknownInput, ()> for TransitionMut<
 impl<'a> IntoTransitionMutParameterized<'a, UnknownInput, ()> for Transition<'a>
 {
     fn into_transition_mut_with(self, _params: ()) -> Result<TransitionMut<'a>,InvalidTransitionError> {
-        Ok(TransitionMut::new(
-            move |args| (self.func)(args),
-            self.requires,
-            self.produces
-        ))
+        Ok(TransitionMut {
+            func: Box::new(move |args| (self.func)(args)),
+            signature: self.signature,
+            guards: self.guards,
+            deferred_guards: self.deferred_guards,
+            priority: self.priority
+        })
     }
 }
 
-impl<'a,In,Param,F> IntoTransitionMutParameterized<'a,In,Param> for F
+impl<'a,In,M,Param,F> IntoTransitionMutParameterized<'a,(In,M),Param> for F
 where 
-    In: TransitionInput,
+    In: TransitionInput<M>,
     Param: Clone + 'a,
-    F: TransitionFunctionMut<In,Param> + 'a
+    F: TransitionFunctionMut<In,M,Param> + 'a
 {
     fn into_transition_mut_with(mut self, params: Param) -> Result<TransitionMut<'a>,InvalidTransitionError> {
         Ok(TransitionMut::new(
             move |args| {
-                let input = <In>::try_take_from(args)?;
-                let res = self.call(input, params.clone());
-                res.insert_into(args);
-                Ok(())
+                let mut fetch = In::fetch(args)?;
+                let res = self.call(In::item(&mut fetch), params.clone());
+                In::restore(fetch, args);
+                res.insert_into(args)
             },
-            <In>::required()?,
-            F::Result::produces()?
+            Signature::of::<In,M,F::Result>()?
         ))
     }
 }
@@ -153,41 +176,44 @@ impl<'a> IntoTransitionOnceParameterized<'a, UnknownInput, ()> for TransitionOnc
 impl<'a> IntoTransitionOnceParameterized<'a, UnknownInput, ()> for TransitionMut<'a>
 {
     fn into_transition_once_with(mut self, _params: ()) -> Result<TransitionOnce<'a>,InvalidTransitionError> {
-        Ok(TransitionOnce::new(
-            move |args| (self.func)(args),
-            self.requires,
-            self.produces
-        ))
+        Ok(TransitionOnce {
+            func: Box::new(move |args| (self.func)(args)),
+            signature: self.signature,
+            guards: self.guards,
+            deferred_guards: self.deferred_guards,
+            priority: self.priority
+        })
     }
 }
 
 impl<'a> IntoTransitionOnceParameterized<'a, UnknownInput, ()> for Transition<'a>
 {
     fn into_transition_once_with(self, _params: ()) -> Result<TransitionOnce<'a>,InvalidTransitionError> {
-        Ok(TransitionOnce::new(
-            self.func,
-            self.requires,
-            self.produces
-        ))
+        Ok(TransitionOnce {
+            func: self.func,
+            signature: self.signature,
+            guards: self.guards,
+            deferred_guards: self.deferred_guards,
+            priority: self.priority
+        })
     }
 }
 
-impl<'a,In,Param,F> IntoTransitionOnceParameterized<'a,In,Param> for F
+impl<'a,In,M,Param,F> IntoTransitionOnceParameterized<'a,(In,M),Param> for F
 where 
-    In: TransitionInput,
+    In: TransitionInput<M>,
     Param: 'a,
-    F: TransitionFunctionOnce<In,Param> + 'a
+    F: TransitionFunctionOnce<In,M,Param> + 'a
 {
     fn into_transition_once_with(self, params: Param) -> Result<TransitionOnce<'a>,InvalidTransitionError> {
         Ok(TransitionOnce::new(
             move |args| {
-                let input = <In>::try_take_from(args)?;
-                let res = self.call(input, params);
-                res.insert_into(args);
-                Ok(())
+                let mut fetch = In::fetch(args)?;
+                let res = self.call(In::item(&mut fetch), params);
+                In::restore(fetch, args);
+                res.insert_into(args)
             },
-            <In>::required()?,
-            F::Result::produces()?
+            Signature::of::<In,M,F::Result>()?
         ))
     }
 }
@@ -197,8 +223,95 @@ where
 /// This trait is implemented for:
 /// 
 /// - The `Transition` type.
-/// - `Fn` types that take up to 4 inputs of types that implement `TransitionInput`
-///     and return a type that implements `TransitionOutput`.
+/// - `Fn` types that take up to 24 inputs of types that implement `TransitionInput`
+///   and return a type that implements `TransitionOutput`.
+/// 
+/// Inputs of type `&T` or `&mut T` borrow the truth `T` instead of taking it out of the state.
+/// The truth is still required to run the transition, but it stays in the state afterwards.
+/// 
+/// Functions returning a `Result<Output, E>` are fallible. When such a function returns an error, its inputs go back
+/// into the state: borrowed inputs are put back, and inputs it took by value are restored from a copy made when they were
+/// taken. Only truths that can be cloned, for example by deriving `Truth` with `#[truth(clone)]`, can be restored this way.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth,Debug)]
+/// struct Counter(i32);
+/// 
+/// #[derive(Truth,Debug)]
+/// struct Report(i32);
+/// 
+/// fn increment(counter: &mut Counter) {
+///     counter.0 += 1;
+/// }
+/// 
+/// fn report(counter: &Counter) -> Report {
+///     Report(counter.0)
+/// }
+/// 
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Counter(0));
+/// 
+/// state_machine.run(increment.into_transition().unwrap()).unwrap();
+/// state_machine.run(increment.and_then(report).unwrap()).unwrap();
+/// 
+/// assert_eq!(state_machine.unset_truth::<Counter>().unwrap().0, 2);
+/// assert_eq!(state_machine.unset_truth::<Report>().unwrap().0, 2);
+/// ```
+/// 
+/// A transition function can be fallible by returning `Result<Out, E>`, where `E` can be converted into
+/// `Box<dyn Error + Send + Sync>`, like any type implementing `Error`, `String` or `&str`. Other error types,
+/// like `()`, have to be wrapped in such a type. If the function returns an error, the transition fails with
+/// `TransitionCallError::Failed`, and leaves the truths it took by value in the state.
+/// 
+/// ```
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth,Clone,Debug)]
+/// #[truth(clone)]
+/// struct Order(u32);
+/// 
+/// #[derive(Truth,Debug)]
+/// struct Shipped(u32);
+/// 
+/// fn ship(order: Order) -> Result<Shipped, String> {
+///     Err(format!("order {} is out of stock", order.0))
+/// }
+/// 
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Order(1));
+/// 
+/// assert!(matches!(state_machine.run(ship), Err(TransitionCallError::Failed(_))));
+/// assert!(!state_machine.has_truth::<Shipped>());
+/// assert_eq!(state_machine.unset_truth::<Order>().unwrap().0, 1);
+/// ```
+/// 
+/// The truths are put back from copies, which are taken before the function runs. A fallible function that takes
+/// a truth by value or through a `&mut` reference which can not be cloned is not run, and fails with
+/// `TransitionCallError::NotRestorable` instead, so that the truth is not lost.
+/// 
+/// ```
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth,Debug)]
+/// struct Connection(u32);
+/// 
+/// #[derive(Truth,Debug)]
+/// struct Closed(u32);
+/// 
+/// fn close(connection: Connection) -> Result<Closed, String> {
+///     Err(format!("connection {} is busy", connection.0))
+/// }
+/// 
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Connection(1));
+/// 
+/// assert!(matches!(state_machine.run(close), Err(TransitionCallError::NotRestorable(_))));
+/// assert_eq!(state_machine.unset_truth::<Connection>().unwrap().0, 1);
+/// ```
 pub trait IntoTransition<'a,In> {
     fn into_transition(self) -> Result<Transition<'a>,InvalidTransitionError>;
 }
@@ -209,8 +322,8 @@ pub trait IntoTransition<'a,In> {
 /// 
 /// - The `TransitionMut` type.
 /// - The `Transition` type.
-/// - `FnMut` types that take up to 4 inputs of types that implement `TransitionInput`
-///    and return a type that implements `TransitionOutput`.
+/// - `FnMut` types that take up to 24 inputs of types that implement `TransitionInput`
+///   and return a type that implements `TransitionOutput`.
 pub trait IntoTransitionMut<'a,In> {
     fn into_transition_mut(self) -> Result<TransitionMut<'a>,InvalidTransitionError>;
 }
@@ -222,7 +335,7 @@ pub trait IntoTransitionMut<'a,In> {
 /// - The `TransitionOnce` type.
 /// - The `TransitionMut` type.
 /// - The `Transition` type.
-/// - `FnOnce` types that take up to 4 inputs of types that implement `TransitionInput`
+/// - `FnOnce` types that take up to 24 inputs of types that implement `TransitionInput`
 ///   and return a type that implements `TransitionOutput`.
 pub trait IntoTransitionOnce<'a,In> {
     fn into_transition_once(self) -> Result<TransitionOnce<'a>,InvalidTransitionError>;
@@ -350,7 +463,6 @@ macro_rules! into_transition_mut_with {
 /// let a = state_machine.unset_truth::<A>().unwrap();
 /// assert_eq!(a.0, 42);
 /// ```
-
 #[macro_export]
 macro_rules! into_transition_once_with {
     ($transition:ident, $($param:expr),*) => {
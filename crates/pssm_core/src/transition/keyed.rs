@@ -0,0 +1,565 @@
+use std::{any::{Any, TypeId}, collections::{BTreeMap, HashMap}, marker::PhantomData, ops::{Deref, DerefMut}};
+
+use crate::{Id, State, StateView, Truth};
+
+use super::{IntoTransition, IntoTransitionMut, IntoTransitionOnce, InvalidTransitionError, Transition, TransitionError, TransitionMut, TransitionOnce};
+use super::asynchronous::AsyncInput;
+use super::function::{Borrowed, BorrowedMut, InputMarker, Owned, TransitionInput, TransitionOutput};
+use super::send::{send_truth, SendInput, SendOutput, Sender};
+
+/// A truth stored in the state under a key.
+///
+/// The state holds at most one truth of every type, but any number of keyed truths of the same type,
+/// one for every key. Keyed truths are set with `StateMachine::set_truth_keyed`, and taken or produced by transitions
+/// as `Keyed<K, T>` inputs and outputs:
+///
+/// - `Keyed<K, T>` takes the truth with the smallest key out of the state.
+/// - `&Keyed<K, T>` and `&mut Keyed<K, T>` borrow the truth with the smallest key.
+/// - A `Keyed<K, T>` output inserts the truth under its key, replacing only a truth with the same key.
+///
+/// To take or borrow the truth stored under another key, the transition is selected by key with `ForKey`.
+///
+/// Signatures track keyed truths per type: a transition taking a `Keyed<K, T>` requires some truth of type `T` to be stored
+/// under any key of type `K`, identified by `TruthId::of_keyed::<K, T>()`. Keyed truths and unkeyed truths of the same type
+/// are tracked separately. Since all truths of type `T` with keys of type `K` are one truth of the signature,
+/// a fallible transition taking one of them by value or through a `&mut` reference is only run if all of them can be cloned,
+/// see `StateMachine::run`.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct Order(u32);
+///
+/// #[derive(Truth,Debug)]
+/// struct Shipment(u32);
+///
+/// fn ship(order: Keyed<u64, Order>) -> Keyed<u64, Shipment> {
+///     let (id, order) = order.into_parts();
+///     Keyed::new(id, Shipment(order.0))
+/// }
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth_keyed(7u64, Order(10));
+/// state_machine.set_truth_keyed(3u64, Order(20));
+///
+/// state_machine.run(ship).unwrap();
+///
+/// assert!(state_machine.has_truth_keyed::<u64, Shipment>(&3));
+/// assert!(!state_machine.has_truth_keyed::<u64, Order>(&3));
+/// assert_eq!(state_machine.unset_truth_keyed::<u64, Order>(&7).unwrap().0, 10);
+/// ```
+///
+/// A fallible transition that takes a keyed truth which can not be cloned is not run, so the truth is not lost:
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct Order(u32);
+///
+/// fn cancel(order: Keyed<u64, Order>) -> Result<(), String> {
+///     Err(format!("order {} is already shipped", order.key()))
+/// }
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth_keyed(3u64, Order(20));
+///
+/// assert!(matches!(state_machine.run(cancel), Err(TransitionCallError::NotRestorable(_))));
+/// assert_eq!(state_machine.unset_truth_keyed::<u64, Order>(&3).unwrap().0, 20);
+/// ```
+pub struct Keyed<K,T> {
+    key: K,
+    truth: T
+}
+
+impl<K,T> Keyed<K,T> {
+    /// Creates a keyed truth.
+    pub fn new(key: K, truth: T) -> Self {
+        Self { key, truth }
+    }
+
+    /// Returns the key of this truth.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns the key and the truth.
+    pub fn into_parts(self) -> (K, T) {
+        (self.key, self.truth)
+    }
+}
+
+impl<K,T> Deref for Keyed<K,T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.truth
+    }
+}
+
+impl<K,T> DerefMut for Keyed<K,T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.truth
+    }
+}
+
+/// A trait for selecting the key of the keyed truths a transition takes.
+///
+/// `for_key::<K, T>(key)` converts the object into a `Transition` whose `Keyed<K, T>`, `&Keyed<K, T>` and `&mut Keyed<K, T>`
+/// inputs take or borrow the truth stored under the given key, instead of the truth with the smallest key.
+/// The transition can only be run if a truth of type `T` is stored under the key, which is checked like a guard.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct Order(u32);
+///
+/// #[derive(Truth,Debug)]
+/// struct Shipment(u32);
+///
+/// fn ship(order: Keyed<u64, Order>) -> Keyed<u64, Shipment> {
+///     let (id, order) = order.into_parts();
+///     Keyed::new(id, Shipment(order.0))
+/// }
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth_keyed(3u64, Order(20));
+/// state_machine.set_truth_keyed(7u64, Order(10));
+///
+/// let ship_7 = ship.for_key::<u64, Order>(7).unwrap();
+/// let ship_9 = ship.for_key::<u64, Order>(9).unwrap();
+/// assert!(state_machine.can_run_transition(&ship_7));
+/// assert!(!state_machine.can_run_transition(&ship_9));
+///
+/// state_machine.run_ref(&ship_7).unwrap();
+///
+/// assert!(state_machine.has_truth_keyed::<u64, Shipment>(&7));
+/// assert!(!state_machine.has_truth_keyed::<u64, Order>(&7));
+/// assert_eq!(state_machine.unset_truth_keyed::<u64, Order>(&3).unwrap().0, 20);
+/// ```
+pub trait ForKey<'a,In> {
+    /// Converts this object into a `Transition` that takes the keyed truths of type `T` stored under the given key.
+    fn for_key<K,T>(self, key: K) -> Result<Transition<'a>,InvalidTransitionError>
+    where
+        K: Ord + Clone + 'static,
+        T: Truth + 'static;
+}
+
+/// A trait for selecting the key of the keyed truths a mutable transition takes.
+///
+/// See `ForKey` for more details.
+pub trait ForKeyMut<'a,In> {
+    /// Converts this object into a `TransitionMut` that takes the keyed truths of type `T` stored under the given key.
+    fn for_key_mut<K,T>(self, key: K) -> Result<TransitionMut<'a>,InvalidTransitionError>
+    where
+        K: Ord + Clone + 'static,
+        T: Truth + 'static;
+}
+
+/// A trait for selecting the key of the keyed truths a transition that can only be run once takes.
+///
+/// See `ForKey` for more details.
+pub trait ForKeyOnce<'a,In> {
+    /// Converts this object into a `TransitionOnce` that takes the keyed truths of type `T` stored under the given key.
+    fn for_key_once<K,T>(self, key: K) -> Result<TransitionOnce<'a>,InvalidTransitionError>
+    where
+        K: Ord + Clone + 'static,
+        T: Truth + 'static;
+}
+
+impl<'a,I,In> ForKey<'a,In> for I
+where
+    I: IntoTransition<'a,In>
+{
+    fn for_key<K,T>(self, key: K) -> Result<Transition<'a>,InvalidTransitionError>
+    where
+        K: Ord + Clone + 'static,
+        T: Truth + 'static
+    {
+        let mut transition = self.into_transition()?;
+        let func = transition.func;
+        let id = Id::of_keyed::<K,T>();
+        let selected = key.clone();
+        transition.guards.push(Box::new(move |state: &StateView| state.has_keyed::<K,T>(&selected)));
+        transition.func = Box::new(move |state| state.with_key(id, Box::new(key.clone()), |state| func(state)));
+        Ok(transition)
+    }
+}
+
+impl<'a,I,In> ForKeyMut<'a,In> for I
+where
+    I: IntoTransitionMut<'a,In>
+{
+    fn for_key_mut<K,T>(self, key: K) -> Result<TransitionMut<'a>,InvalidTransitionError>
+    where
+        K: Ord + Clone + 'static,
+        T: Truth + 'static
+    {
+        let mut transition = self.into_transition_mut()?;
+        let mut func = transition.func;
+        let id = Id::of_keyed::<K,T>();
+        let selected = key.clone();
+        transition.guards.push(Box::new(move |state: &StateView| state.has_keyed::<K,T>(&selected)));
+        transition.func = Box::new(move |state| state.with_key(id, Box::new(key.clone()), |state| func(state)));
+        Ok(transition)
+    }
+}
+
+impl<'a,I,In> ForKeyOnce<'a,In> for I
+where
+    I: IntoTransitionOnce<'a,In>
+{
+    fn for_key_once<K,T>(self, key: K) -> Result<TransitionOnce<'a>,InvalidTransitionError>
+    where
+        K: Ord + Clone + 'static,
+        T: Truth + 'static
+    {
+        let mut transition = self.into_transition_once()?;
+        let func = transition.func;
+        let id = Id::of_keyed::<K,T>();
+        let selected = key.clone();
+        transition.guards.push(Box::new(move |state: &StateView| state.has_keyed::<K,T>(&selected)));
+        transition.func = Box::new(move |state| state.with_key(id, Box::new(key), func));
+        Ok(transition)
+    }
+}
+
+/// The keyed truths of one type, stored in the state as a single truth.
+pub(crate) struct KeyedTruths<K,T>(BTreeMap<K,T>);
+
+impl<K,T> Truth for KeyedTruths<K,T>
+where
+    K: Ord + Clone + 'static,
+    T: Truth + 'static
+{
+    fn id() -> TypeId {
+        TypeId::of::<Self>()
+    }
+
+    fn name() -> &'static str {
+        std::any::type_name::<Keyed<K,T>>()
+    }
+
+    fn try_clone(&self) -> Option<Self> {
+        self.0.iter()
+            .map(|(key, truth)| truth.try_clone().map(|truth| (key.clone(), truth)))
+            .collect::<Option<_>>()
+            .map(KeyedTruths)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K: serde::Serialize, T: serde::Serialize> serde::Serialize for KeyedTruths<K,T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K: serde::Deserialize<'de> + Ord, T: serde::Deserialize<'de>> serde::Deserialize<'de> for KeyedTruths<K,T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        BTreeMap::deserialize(deserializer).map(KeyedTruths)
+    }
+}
+
+/// Marker for keyed inputs, wrapping the marker of how the keyed truth is taken.
+pub struct ByKey<M>(PhantomData<M>);
+
+impl<M> InputMarker for ByKey<M> {}
+
+fn truths_mut<K,T>(state: &mut State) -> Option<&mut BTreeMap<K,T>>
+where
+    K: Ord + Clone + 'static,
+    T: Truth + 'static
+{
+    state.get_mut(&Id::of_keyed::<K,T>())
+        .map(|truths| &mut truths.downcast_mut::<KeyedTruths<K,T>>().expect("Invalid type stored for a truth in the state").0)
+}
+
+/// Checks if a truth is stored under the given key.
+pub(crate) fn contains_keyed<K,T>(state: &State, key: &K) -> bool
+where
+    K: Ord + Clone + 'static,
+    T: Truth + 'static
+{
+    state.get(&Id::of_keyed::<K,T>())
+        .is_some_and(|truths| truths.downcast_ref::<KeyedTruths<K,T>>().expect("Invalid type stored for a truth in the state").0.contains_key(key))
+}
+
+/// Returns the truth stored under the given key.
+pub(crate) fn get_keyed<'s,K,T>(state: &'s State, key: &K) -> Option<&'s T>
+where
+    K: Ord + Clone + 'static,
+    T: Truth + 'static
+{
+    state.get(&Id::of_keyed::<K,T>())
+        .and_then(|truths| truths.downcast_ref::<KeyedTruths<K,T>>().expect("Invalid type stored for a truth in the state").0.get(key))
+}
+
+/// Takes the truth stored under the given key out of the state. If no key is given, the key selected with `ForKey` is used,
+/// or the truth with the smallest key is taken if none is selected.
+///
+/// Like `State::remove`, a copy of the truth is recorded in a transaction if it can be cloned, so that it can be put back.
+/// Fallible transitions are only run if the keyed truths they take can be cloned, see `Signature::check_restorable`.
+pub(crate) fn take_keyed<K,T>(state: &mut State, key: Option<&K>) -> Result<Keyed<K,T>, TransitionError>
+where
+    K: Ord + Clone + 'static,
+    T: Truth + 'static
+{
+    let id = Id::of_keyed::<K,T>();
+    let key = key.cloned().or_else(|| state.key(&id).and_then(|key| key.downcast_ref::<K>()).cloned());
+    let truths = truths_mut::<K,T>(state).ok_or(TransitionError::MissingTruth(id))?;
+    let (key, truth) = match key {
+        Some(key) => truths.remove_entry(&key),
+        None => truths.pop_first()
+    }.ok_or(TransitionError::MissingTruth(id))?;
+
+    let empty = truths.is_empty();
+    if let Some(copy) = truth.try_clone() {
+        let key = key.clone();
+        state.taken(id, Box::new(move |state: &mut HashMap<Id, Box<dyn Any>>| {
+            let truths = state.entry(id).or_insert_with(|| Box::new(KeyedTruths::<K,T>(BTreeMap::new())));
+            if let Some(truths) = truths.downcast_mut::<KeyedTruths<K,T>>() {
+                truths.0.insert(key, copy);
+            }
+        }));
+    }
+    if empty {
+        state.remove(&id);
+    }
+    Ok(Keyed { key, truth })
+}
+
+/// Puts a keyed truth that was taken out of the state, but not consumed, back into the state.
+fn put_back_keyed<K,T>(state: &mut State, keyed: Keyed<K,T>)
+where
+    K: Ord + Clone + 'static,
+    T: Truth + 'static
+{
+    match truths_mut::<K,T>(state) {
+        Some(truths) => { truths.insert(keyed.key, keyed.truth); },
+        None => state.put_back(Id::of_keyed::<K,T>(), Box::new(KeyedTruths(BTreeMap::from([(keyed.key, keyed.truth)]))))
+    }
+}
+
+/// Inserts a keyed truth into the state, replacing only the truth with the same key.
+pub(crate) fn insert_keyed<K,T>(state: &mut State, keyed: Keyed<K,T>)
+where
+    K: Ord + Clone + 'static,
+    T: Truth + 'static
+{
+    let id = Id::of_keyed::<K,T>();
+    let Some(truths) = truths_mut::<K,T>(state) else {
+        state.insert(id, Box::new(KeyedTruths(BTreeMap::from([(keyed.key, keyed.truth)]))));
+        return;
+    };
+
+    let key = keyed.key.clone();
+    let replaced = truths.insert(keyed.key, keyed.truth);
+    state.changed(id, Box::new(move |state: &mut HashMap<Id, Box<dyn Any>>| {
+        let Some(truths) = state.get_mut(&id).and_then(|truths| truths.downcast_mut::<KeyedTruths<K,T>>()) else {
+            return;
+        };
+        match replaced {
+            Some(truth) => { truths.0.insert(key, truth); },
+            None => {
+                truths.0.remove(&key);
+                if truths.0.is_empty() {
+                    state.remove(&id);
+                }
+            }
+        }
+    }));
+}
+
+impl<K,T> TransitionInput<ByKey<Owned>> for Keyed<K,T>
+where
+    K: Ord + Clone + 'static,
+    T: Truth + 'static
+{
+    type Fetch = Option<Keyed<K,T>>;
+    type Item<'f> = Keyed<K,T>;
+
+    fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+        take_keyed(state, None).map(Some)
+    }
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+        fetch.take().expect("A truth was handed to a transition function twice")
+    }
+
+    fn restore(fetch: Self::Fetch, state: &mut State) {
+        if let Some(keyed) = fetch {
+            put_back_keyed(state, keyed);
+        }
+    }
+
+    fn collect_required<C,E>(collector: &mut C) -> Result<(),E>
+    where
+        C: FnMut(Id) -> Result<(),E>
+    {
+        collector(Id::of_keyed::<K,T>())
+    }
+
+    fn collect_borrowed<C>(_: &mut C)
+    where
+        C: FnMut(Id)
+    {}
+
+    fn collect_optional<C>(_: &mut C)
+    where
+        C: FnMut(Id)
+    {}
+
+    fn collect_forbidden<C>(_: &mut C)
+    where
+        C: FnMut(Id)
+    {}
+}
+
+macro_rules! impl_keyed_borrowed {
+    ($M:ident, $Ref:ty, $Item:ty, $mutated:literal) => {
+        impl<K,T> TransitionInput<ByKey<$M>> for $Ref
+        where
+            K: Ord + Clone + 'static,
+            T: Truth + 'static
+        {
+            type Fetch = Keyed<K,T>;
+            type Item<'f> = $Item;
+
+            fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+                take_keyed(state, None)
+            }
+
+            fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+                fetch
+            }
+
+            fn restore(fetch: Self::Fetch, state: &mut State) {
+                put_back_keyed(state, fetch);
+            }
+
+            fn collect_required<C,E>(collector: &mut C) -> Result<(),E>
+            where
+                C: FnMut(Id) -> Result<(),E>
+            {
+                collector(Id::of_keyed::<K,T>())
+            }
+
+            fn collect_borrowed<C>(collector: &mut C)
+            where
+                C: FnMut(Id)
+            {
+                collector(Id::of_keyed::<K,T>())
+            }
+
+            fn collect_optional<C>(_: &mut C)
+            where
+                C: FnMut(Id)
+            {}
+
+            fn collect_forbidden<C>(_: &mut C)
+            where
+                C: FnMut(Id)
+            {}
+
+            fn collect_mutated<C>(collector: &mut C)
+            where
+                C: FnMut(Id)
+            {
+                if $mutated {
+                    collector(Id::of_keyed::<K,T>())
+                }
+            }
+        }
+    };
+}
+
+impl_keyed_borrowed!(Borrowed, &Keyed<K,T>, &'f Keyed<K,T>, false);
+impl_keyed_borrowed!(BorrowedMut, &mut Keyed<K,T>, &'f mut Keyed<K,T>, true);
+
+impl<K,T> TransitionOutput for Keyed<K,T>
+where
+    K: Ord + Clone + 'static,
+    T: Truth + 'static
+{
+    fn insert_into(self, state: &mut State) -> Result<(), TransitionError> {
+        insert_keyed(state, self);
+        Ok(())
+    }
+
+    fn collect_produces<C,E>(mut collector: C) -> Result<(),E>
+    where
+        C: FnMut(Id) -> Result<(),E>
+    {
+        collector(Id::of_keyed::<K,T>())
+    }
+}
+
+impl<K,T> AsyncInput<ByKey<Owned>> for Keyed<K,T>
+where
+    K: Ord + Clone + 'static,
+    T: Truth + 'static
+{
+    fn take(fetch: Self::Fetch) -> Self {
+        fetch.expect("A truth was handed to a transition function twice")
+    }
+}
+
+impl<K,T> SendInput<ByKey<Owned>> for Keyed<K,T>
+where
+    K: Ord + Clone + Send + 'static,
+    T: Truth + Send + 'static
+{
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        collector(Id::of_keyed::<K,T>(), send_truth::<KeyedTruths<K,T>>)
+    }
+}
+
+impl<K,T> SendInput<ByKey<Borrowed>> for &Keyed<K,T>
+where
+    K: Ord + Clone + Send + 'static,
+    T: Truth + Send + 'static
+{
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        collector(Id::of_keyed::<K,T>(), send_truth::<KeyedTruths<K,T>>)
+    }
+}
+
+impl<K,T> SendInput<ByKey<BorrowedMut>> for &mut Keyed<K,T>
+where
+    K: Ord + Clone + Send + 'static,
+    T: Truth + Send + 'static
+{
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        collector(Id::of_keyed::<K,T>(), send_truth::<KeyedTruths<K,T>>)
+    }
+}
+
+impl<K,T> SendOutput for Keyed<K,T>
+where
+    K: Ord + Clone + Send + 'static,
+    T: Truth + Send + 'static
+{
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        collector(Id::of_keyed::<K,T>(), send_truth::<KeyedTruths<K,T>>)
+    }
+}
@@ -1,12 +1,40 @@
-use crate::{Id, State};
-use std::{collections::HashSet, fmt::Debug};
+use crate::{Id, State, StateView};
+use std::{collections::HashSet, error::Error, fmt::{Debug, Display}};
+
+// Invokes the given macro once for every prefix of the given list, from the empty prefix up to the whole list.
+// `for_each_prefix!(impl_trans_out, A1, A2)` expands to `impl_trans_out!()`, `impl_trans_out!(A1)` and `impl_trans_out!(A1, A2)`.
+// An element of the list can be made of several identifiers, like `A1 M1`.
+macro_rules! for_each_prefix {
+    ($m:ident, $($($x:ident)+),*) => {
+        for_each_prefix!($m; []; $($($x)+),*);
+    };
+    ($m:ident; [$($($done:ident)+),*];) => {
+        $m!($($($done)+),*);
+    };
+    ($m:ident; [$($($done:ident)+),*]; $($next:ident)+ $(, $($rest:ident)+)*) => {
+        $m!($($($done)+),*);
+        for_each_prefix!($m; [$($($done)+,)* $($next)+]; $($($rest)+),*);
+    };
+}
 
 mod andthen;
+mod asynchronous;
+mod choice;
 pub(crate) mod function;
+mod guard;
 mod into;
+pub(crate) mod keyed;
+mod send;
+mod tagged;
 
-pub use andthen::{AndThen, AndThenMut, AndThenOnce};
-pub use function::Param;
+use function::{TransitionInput, TransitionOutput};
+
+pub use andthen::{AndThen, AndThenError, AndThenMut, AndThenOnce};
+pub use asynchronous::{AsyncTransition, CompletedTransition, IntoAsyncTransition, IntoAsyncTransitionParameterized, PendingTransition};
+pub use choice::{Alternatives, Either, OneOf};
+pub use function::{Absent, Param};
+pub use guard::{Guard, GuardMut, GuardOnce};
+pub use keyed::{ForKey, ForKeyMut, ForKeyOnce, Keyed};
 pub use into::{
     IntoTransition,
     IntoTransitionMut,
@@ -16,18 +44,75 @@ pub use into::{
     IntoTransitionParameterized,
     UnknownInput,
 };
+pub use send::{IntoSendTransition, IntoSendTransitionParameterized, SendTransition};
+pub use tagged::{Tag, Tagged};
 
 /// An Error that can occur when running a transition.
 /// 
-/// This error can occur when a transition is run on a state that does not contain all of the required truths for the transition.
+/// This error can occur when a transition is run on a state that does not contain all of the required truths for the transition,
+/// or none of several alternative truths, when the state contains a truth that the transition requires to be absent, when a guard of the transition rejects the state,
+/// when a truth the transition needs is held by another running transition,
+/// or when a fallible transition function returns an error.
+/// 
+/// Missing truths are reported by their name.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth,Debug)]
+/// struct LoggedIn();
+/// 
+/// let mut state_machine = StateMachine::new();
+/// let error = state_machine.unset_truth::<LoggedIn>().unwrap_err();
+/// 
+/// assert!(matches!(error, TransitionError::MissingTruth(id) if id == TruthId::of::<LoggedIn>()));
+/// assert_eq!(format!("{:?}", error), format!("missing truth `{}`", LoggedIn::name()));
+/// ```
 pub enum TransitionError {
-    MissingTruth(Id)
+    MissingTruth(Id),
+    MissingAlternative(Vec<Id>),
+    ForbiddenTruth(Id),
+    GuardRejected,
+    TruthHeld(Id),
+    Failed(Box<dyn Error + Send + Sync>)
 }
 
 impl Debug for TransitionError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            TransitionError::MissingTruth(id) => write!(f, "A required truth is missing from the State: {:?}", id)
+            TransitionError::Failed(e) => write!(f, "transition function failed: {:?}", e),
+            _ => Display::fmt(self, f)
+        }
+    }
+}
+
+impl Display for TransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransitionError::MissingTruth(id) => write!(f, "missing truth `{}`", id),
+            TransitionError::MissingAlternative(ids) => {
+                let names: Vec<String> = ids.iter().map(|id| format!("`{}`", id)).collect();
+                write!(f, "missing one of the truths {}", names.join(", "))
+            },
+            TransitionError::ForbiddenTruth(id) => write!(f, "forbidden truth `{}` is present", id),
+            TransitionError::GuardRejected => f.write_str("guard rejected the state"),
+            TransitionError::TruthHeld(id) => write!(f, "truth `{}` is held by another transition", id),
+            TransitionError::Failed(_) => f.write_str("transition function failed")
+        }
+    }
+}
+
+impl Error for TransitionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TransitionError::MissingTruth(_)
+                | TransitionError::MissingAlternative(_)
+                | TransitionError::ForbiddenTruth(_)
+                | TransitionError::GuardRejected
+                | TransitionError::TruthHeld(_) => None,
+            TransitionError::Failed(e) => Some(e.as_ref())
         }
     }
 }
@@ -35,21 +120,263 @@ impl Debug for TransitionError {
 /// An Error that can occur when creating a transition from a function.
 /// 
 /// This error can occur when a function is used to create a transition,
-/// but the function requires or produces the same truth multiple times.
+/// but the function requires or produces the same truth multiple times,
+/// requires a truth to be both present and absent, or takes a truth that a `Tagged` input of the function drains as well.
 pub enum InvalidTransitionError {
     TruthRequiredMultipleTimes(Id),
-    TruthProducedMultipleTimes(Id)
+    TruthProducedMultipleTimes(Id),
+    TruthRequiredAndForbidden(Id),
+    TruthRequiredAndDrained(Id)
 }
 
 impl Debug for InvalidTransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for InvalidTransitionError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            InvalidTransitionError::TruthRequiredMultipleTimes(id) => write!(f, "Transition requires the same truth multiple times: {:?}", id),
-            InvalidTransitionError::TruthProducedMultipleTimes(id) => write!(f, "Transition produces the same truth multiple times: {:?}", id)
+            InvalidTransitionError::TruthRequiredMultipleTimes(id) => write!(f, "truth `{}` is required multiple times", id),
+            InvalidTransitionError::TruthProducedMultipleTimes(id) => write!(f, "truth `{}` is produced multiple times", id),
+            InvalidTransitionError::TruthRequiredAndForbidden(id) => write!(f, "truth `{}` is required to be both present and absent", id),
+            InvalidTransitionError::TruthRequiredAndDrained(id) => write!(f, "truth `{}` is taken by an input and drained by a tag", id)
         }
     }
 }
 
+impl Error for InvalidTransitionError {}
+
+type TransitionFn<'a> = dyn Fn(&mut State) -> Result<(), TransitionError> + 'a;
+type TransitionFnMut<'a> = dyn FnMut(&mut State) -> Result<(), TransitionError> + 'a;
+type TransitionFnOnce<'a> = dyn FnOnce(&mut State) -> Result<(), TransitionError> + 'a;
+type GuardFn<'a> = dyn Fn(&StateView) -> bool + 'a;
+
+/// Checks that every guard accepts the state.
+pub(crate) fn check_guards(guards: &[Box<GuardFn>], state: &State) -> Result<(), TransitionError> {
+    let view = StateView::new(state);
+    if guards.iter().all(|guard| guard(&view)) {
+        Ok(())
+    } else {
+        Err(TransitionError::GuardRejected)
+    }
+}
+
+/// The truths a transition reads from and writes to the state.
+/// 
+/// `requires` contains every truth that has to be in the state for the transition to run.
+/// `borrows` is the subset of `requires` that is only borrowed and stays in the state afterwards.
+/// `mutates` contains the borrowed truths that can be changed in place, through a `&mut` reference.
+/// `optional` contains every truth that is not required, but taken out of the state if it is there.
+/// `forbids` contains every truth that has to be absent from the state for the transition to run.
+/// `produces` contains every truth that is inserted into the state by the transition.
+/// `may_produce` contains every truth that is only inserted for some of the values the transition function returns,
+/// like the truths of an `Option` output.
+/// `drains` contains the tags of the truths that are taken out of the state by the transition, whatever their type.
+/// `alternatives` contains lists of truths of which at least one has to be in the state, the first one present is taken or borrowed.
+/// Alternatives that are only borrowed are part of `borrows` as well.
+/// `fallible` tells whether the transition can fail after it changed the state, see `StateMachine::run`.
+/// 
+/// The signature only describes the truths of a transition, it does not take its guards into account.
+/// 
+/// # Examples
+/// 
+/// ```
+/// use pssm::prelude::*;
+/// 
+/// #[derive(Truth,Debug)]
+/// struct A();
+/// 
+/// #[derive(Truth,Debug)]
+/// struct B();
+/// 
+/// fn replace_a(a: A, _: &B) -> A {
+///     a
+/// }
+/// 
+/// let transition = replace_a.into_transition().unwrap();
+/// let signature = transition.signature();
+/// 
+/// assert!(signature.requires().contains(&TruthId::of::<A>()));
+/// assert!(signature.borrows().contains(&TruthId::of::<B>()));
+/// assert!(signature.consumes().contains(&TruthId::of::<A>()));
+/// assert!(signature.produces().contains(&TruthId::of::<A>()));
+/// ```
+#[derive(Clone, Default, Debug)]
+pub struct Signature {
+    pub(crate) requires: HashSet<Id>,
+    pub(crate) borrows: HashSet<Id>,
+    pub(crate) mutates: HashSet<Id>,
+    pub(crate) optional: HashSet<Id>,
+    pub(crate) forbids: HashSet<Id>,
+    pub(crate) produces: HashSet<Id>,
+    pub(crate) may_produce: HashSet<Id>,
+    pub(crate) drains: HashSet<&'static str>,
+    pub(crate) alternatives: Vec<Vec<Id>>,
+    pub(crate) fallible: bool
+}
+
+impl Signature {
+    pub(crate) fn of<In,M,Out>() -> Result<Self, InvalidTransitionError>
+    where 
+        In: TransitionInput<M>,
+        Out: TransitionOutput
+    {
+        let requires = In::required()?;
+        let forbids = In::forbidden();
+        let alternatives = In::alternatives();
+
+        let mut alternative_ids = HashSet::new();
+        for id in alternatives.iter().flatten() {
+            if requires.contains(id) || !alternative_ids.insert(*id) {
+                return Err(InvalidTransitionError::TruthRequiredMultipleTimes(*id));
+            }
+        }
+
+        if let Some(id) = requires.intersection(&forbids).chain(alternative_ids.intersection(&forbids)).next() {
+            return Err(InvalidTransitionError::TruthRequiredAndForbidden(*id));
+        }
+
+        let drains = In::drained();
+        let optional = In::optional();
+        let drained = |id: &&Id| id.tags().iter().any(|tag| drains.contains(tag));
+        if let Some(id) = requires.iter().chain(&optional).chain(&alternative_ids).find(drained) {
+            return Err(InvalidTransitionError::TruthRequiredAndDrained(*id));
+        }
+
+        Ok(Self {
+            requires,
+            borrows: In::borrowed(),
+            mutates: In::mutated(),
+            optional,
+            forbids,
+            produces: Out::produces()?,
+            may_produce: Out::may_produce(),
+            drains,
+            alternatives,
+            fallible: Out::fallible()
+        })
+    }
+
+    /// Checks that no truth the transition touches is reserved, every required truth is in the state and every forbidden truth is not.
+    pub(crate) fn check(&self, state: &State) -> Result<(), TransitionError> {
+        if let Some(id) = self.touches().iter().find(|id| state.is_reserved(id)) {
+            return Err(TransitionError::TruthHeld(*id));
+        }
+        self.check_with(|id| state.contains_key(id))
+    }
+
+    /// Checks that every required truth is present and every forbidden truth is not, using the given function to look up truths.
+    pub(crate) fn check_with<F>(&self, contains: F) -> Result<(), TransitionError>
+    where 
+        F: Fn(&Id) -> bool
+    {
+        if let Some(id) = self.requires.iter().find(|id| !contains(id)) {
+            return Err(TransitionError::MissingTruth(*id));
+        }
+        if let Some(ids) = self.alternatives.iter().find(|ids| !ids.iter().any(&contains)) {
+            return Err(TransitionError::MissingAlternative(ids.clone()));
+        }
+        if let Some(id) = self.forbids.iter().find(|id| contains(id)) {
+            return Err(TransitionError::ForbiddenTruth(*id));
+        }
+        Ok(())
+    }
+
+    /// Checks that the transition can be rolled back if it fails after it changed the state.
+    /// 
+    /// A fallible transition is rolled back from copies of the truths in the state it can take out of the state or change
+    /// in place, so all of them have to be cloneable. Returns the ids of the truths that are not as the error.
+    /// A transition that is not fallible can always be run.
+    pub(crate) fn check_restorable(&self, state: &State) -> Result<(), Vec<Id>> {
+        if !self.fallible {
+            return Ok(());
+        }
+
+        let mut restored = self.consumes();
+        restored.extend(&self.mutates);
+        restored.extend(&self.optional);
+        restored.extend(self.alternatives.iter().flatten().filter(|id| !self.borrows.contains(id)));
+        restored.extend(state.ids().filter(|id| !state.is_reserved(id) && id.tags().iter().any(|tag| self.drains.contains(tag))));
+        state.capture(&restored).map(|_| ())
+    }
+
+    /// Every truth the transition can take out of the state, change or insert into the state.
+    pub(crate) fn touches(&self) -> HashSet<Id> {
+        self.requires.iter()
+            .chain(&self.optional)
+            .chain(&self.produces)
+            .chain(&self.may_produce)
+            .chain(self.alternatives.iter().flatten())
+            .cloned()
+            .collect()
+    }
+
+    /// The truths that have to be in the state for the transition to run.
+    pub fn requires(&self) -> &HashSet<Id> {
+        &self.requires
+    }
+
+    /// The required or alternative truths that are only borrowed, and stay in the state after the transition ran.
+    pub fn borrows(&self) -> &HashSet<Id> {
+        &self.borrows
+    }
+
+    /// The borrowed truths that can be changed in place, through a `&mut` reference.
+    pub fn mutates(&self) -> &HashSet<Id> {
+        &self.mutates
+    }
+
+    /// The truths that are not required, but taken out of the state if they are there.
+    pub fn optional(&self) -> &HashSet<Id> {
+        &self.optional
+    }
+
+    /// The truths that have to be absent from the state for the transition to run.
+    pub fn forbids(&self) -> &HashSet<Id> {
+        &self.forbids
+    }
+
+    /// The truths that are inserted into the state by the transition.
+    pub fn produces(&self) -> &HashSet<Id> {
+        &self.produces
+    }
+
+    /// The truths that are only inserted into the state for some of the values the transition function returns,
+    /// like the truths of an `Option` output.
+    pub fn may_produce(&self) -> &HashSet<Id> {
+        &self.may_produce
+    }
+
+    /// The tags of the truths that are taken out of the state by the transition, whatever their type. See `Tagged`.
+    /// 
+    /// The truths marked with these tags are not part of any other set of the signature.
+    pub fn drains(&self) -> &HashSet<&'static str> {
+        &self.drains
+    }
+
+    /// The lists of truths of which at least one has to be in the state for the transition to run.
+    /// 
+    /// The transition takes or borrows the first truth of every list that is in the state. See `Either` and `OneOf`.
+    pub fn alternatives(&self) -> &[Vec<Id>] {
+        &self.alternatives
+    }
+
+    /// The required truths that are taken out of the state by the transition.
+    pub fn consumes(&self) -> HashSet<Id> {
+        self.requires.difference(&self.borrows).cloned().collect()
+    }
+
+    /// Whether the transition can fail after it changed the state.
+    /// 
+    /// A transition is fallible if a transition function returns a `Result`, or if it is a chain whose later steps
+    /// can fail, because they have guards or may miss truths the earlier steps took. See `StateMachine::run`.
+    pub fn fallible(&self) -> bool {
+        self.fallible
+    }
+}
+
 /// A transition is a function that can be executed on a state.
 /// 
 /// Transitions can be used to change the state of a state machine.
@@ -59,9 +386,12 @@ impl Debug for InvalidTransitionError {
 /// For transitions that have side-effects, see `TransitionMut`.
 /// For transitions that can only be run once, see `TransitionOnce`.
 pub struct Transition<'a> {
-    pub(crate) func: Box<dyn Fn(&mut State) -> Result<(), TransitionError> + 'a>,
-    pub(crate) requires: HashSet<crate::Id>,
-    pub(crate) produces: HashSet<crate::Id>
+    pub(crate) func: Box<TransitionFn<'a>>,
+    pub(crate) signature: Signature,
+    pub(crate) guards: Vec<Box<GuardFn<'a>>>,
+    /// Whether a later step of a chain has guards, which can only be checked after the earlier steps ran.
+    pub(crate) deferred_guards: bool,
+    pub(crate) priority: i32
 }
 
 /// A transition is a function that can be executed on a state.
@@ -73,9 +403,12 @@ pub struct Transition<'a> {
 /// For transitions that are side-effect free, see `Transition`.
 /// For transitions that can only be run once, see `TransitionOnce`.
 pub struct TransitionMut<'a> {
-    pub(crate) func: Box<dyn FnMut(&mut State) -> Result<(), TransitionError> + 'a>,
-    pub(crate) requires: HashSet<crate::Id>,
-    pub(crate) produces: HashSet<crate::Id>
+    pub(crate) func: Box<TransitionFnMut<'a>>,
+    pub(crate) signature: Signature,
+    pub(crate) guards: Vec<Box<GuardFn<'a>>>,
+    /// Whether a later step of a chain has guards, which can only be checked after the earlier steps ran.
+    pub(crate) deferred_guards: bool,
+    pub(crate) priority: i32
 }
 
 /// A transition is a function that can be executed on a state.
@@ -86,22 +419,26 @@ pub struct TransitionMut<'a> {
 /// This transition can only be run once.
 /// For transitions that are side-effect free, see `Transition`.
 /// For transitions that have side-effects, see `TransitionMut`.
-
 pub struct TransitionOnce<'a> {
-    pub(crate) func: Box<dyn FnOnce(&mut State) -> Result<(), TransitionError> + 'a>,
-    pub(crate) requires: HashSet<crate::Id>,
-    pub(crate) produces: HashSet<crate::Id>
+    pub(crate) func: Box<TransitionFnOnce<'a>>,
+    pub(crate) signature: Signature,
+    pub(crate) guards: Vec<Box<GuardFn<'a>>>,
+    /// Whether a later step of a chain has guards, which can only be checked after the earlier steps ran.
+    pub(crate) deferred_guards: bool,
+    pub(crate) priority: i32
 }
 
 impl<'a> Transition<'a> {
-    pub(crate) fn new<F>(func: F, requires: HashSet<Id>, produces: HashSet<Id>) -> Self 
+    pub(crate) fn new<F>(func: F, signature: Signature) -> Self 
     where 
         F: Fn(&mut State) -> Result<(), TransitionError> + 'a
     {
         Self {
             func: Box::new(func),
-            requires,
-            produces
+            signature,
+            guards: Vec::new(),
+            deferred_guards: false,
+            priority: 0
         }
     }
 
@@ -109,67 +446,126 @@ impl<'a> Transition<'a> {
         (self.func)(state)
     }
 
-    pub(crate) fn requires(&self) -> &HashSet<Id> {
-        &self.requires
+    /// Sets the priority of this transition.
+    /// 
+    /// Transitions with a higher priority are preferred when several transitions want to take the same truths,
+    /// see `TransitionDictionary::resolve_conflicts`. The default priority is 0.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Returns the priority of this transition.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns the truths this transition reads from and writes to the state.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Checks the signature and the guards of this transition against the state, without changing it.
+    pub(crate) fn check(&self, state: &State) -> Result<(), TransitionError> {
+        self.signature.check(state)?;
+        check_guards(&self.guards, state)
     }
 }
 
 impl<'a> TransitionMut<'a> {
-    pub(crate) fn new<F>(func: F, requires: HashSet<Id>, produces: HashSet<Id>) -> Self 
+    pub(crate) fn new<F>(func: F, signature: Signature) -> Self 
     where 
         F: FnMut(&mut State) -> Result<(), TransitionError> + 'a
     {
         Self {
             func: Box::new(func),
-            requires,
-            produces
+            signature,
+            guards: Vec::new(),
+            deferred_guards: false,
+            priority: 0
         }
     }
 
-    pub(crate) fn run(&mut self, state: &mut State) -> Result<(), TransitionError> {
-        (self.func)(state)
+    /// Sets the priority of this transition.
+    /// 
+    /// Transitions with a higher priority are preferred when several transitions want to take the same truths,
+    /// see `TransitionDictionary::resolve_conflicts`. The default priority is 0.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
     }
 
-    pub(crate) fn requires(&self) -> &HashSet<Id> {
-        &self.requires
+    /// Returns the priority of this transition.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns the truths this transition reads from and writes to the state.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Checks the signature and the guards of this transition against the state, without changing it.
+    pub(crate) fn check(&self, state: &State) -> Result<(), TransitionError> {
+        self.signature.check(state)?;
+        check_guards(&self.guards, state)
     }
 }
 
 impl<'a> TransitionOnce<'a> {
-    pub(crate) fn new<F>(func: F, requires: HashSet<Id>, produces: HashSet<Id>) -> Self 
+    pub(crate) fn new<F>(func: F, signature: Signature) -> Self 
     where 
         F: FnOnce(&mut State) -> Result<(), TransitionError> + 'a
     {
         Self {
             func: Box::new(func),
-            requires,
-            produces
+            signature,
+            guards: Vec::new(),
+            deferred_guards: false,
+            priority: 0
         }
     }
 
-    pub(crate) fn run(self, state: &mut State) -> Result<(), TransitionError> {
-        (self.func)(state)
+    /// Sets the priority of this transition.
+    /// 
+    /// Transitions with a higher priority are preferred when several transitions want to take the same truths,
+    /// see `TransitionDictionary::resolve_conflicts`. The default priority is 0.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
     }
 
-    pub(crate) fn requires(&self) -> &HashSet<crate::Id> {
-        &self.requires
+    /// Returns the priority of this transition.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns the truths this transition reads from and writes to the state.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Checks the signature and the guards of this transition against the state, without changing it.
+    pub(crate) fn check(&self, state: &State) -> Result<(), TransitionError> {
+        self.signature.check(state)?;
+        check_guards(&self.guards, state)
     }
 }
 
 impl<'a> Debug for Transition<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(format!("Transition({}->{})", self.requires.len(), self.produces.len()).as_str())
+        f.write_str(format!("Transition({}->{})", self.signature.requires.len(), self.signature.produces.len()).as_str())
     }
 }
 
 impl<'a> Debug for TransitionMut<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(format!("TransitionMut({}->{})", self.requires.len(), self.produces.len()).as_str())
+        f.write_str(format!("TransitionMut({}->{})", self.signature.requires.len(), self.signature.produces.len()).as_str())
     }
 }
 
 impl<'a> Debug for TransitionOnce<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(format!("TransitionOnce({}->{})", self.requires.len(), self.produces.len()).as_str())
+        f.write_str(format!("TransitionOnce({}->{})", self.signature.requires.len(), self.signature.produces.len()).as_str())
     }
 }
\ No newline at end of file
@@ -0,0 +1,317 @@
+use std::{any::Any, collections::HashMap, error::Error, fmt::Debug};
+
+use crate::{Id, State, Truth};
+
+use super::{InvalidTransitionError, Signature, TransitionError, UnknownInput};
+use super::function::{Absent, Borrowed, BorrowedMut, Forbidden, Optional, Owned, Param, Parameter, TransitionFunction, TransitionInput, TransitionOutput};
+
+/// Turns a truth that was taken out of a `State` back into a truth that can be sent to another thread.
+pub(crate) type Sender = fn(Box<dyn Any>) -> Box<dyn Any + Send>;
+
+type SendTransitionFn<'a> = dyn Fn(&mut State) -> Result<(), TransitionError> + Send + Sync + 'a;
+
+pub(crate) fn send_truth<T: Truth + Send + 'static>(truth: Box<dyn Any>) -> Box<dyn Any + Send> {
+    truth.downcast::<T>().expect("Invalid type stored for a truth in the state")
+}
+
+/// A transition input that only hands truths which can be sent to other threads to the transition function.
+pub(crate) trait SendInput<M>: TransitionInput<M> {
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender);
+}
+
+impl<T> SendInput<Owned> for T
+where
+    T: Truth + Send + 'static
+{
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        collector(Id::of::<T>(), send_truth::<T>)
+    }
+}
+
+impl<T> SendInput<Borrowed> for &T
+where
+    T: Truth + Send + 'static
+{
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        collector(Id::of::<T>(), send_truth::<T>)
+    }
+}
+
+impl<T> SendInput<BorrowedMut> for &mut T
+where
+    T: Truth + Send + 'static
+{
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        collector(Id::of::<T>(), send_truth::<T>)
+    }
+}
+
+impl<T,M> SendInput<Optional<M>> for Option<T>
+where
+    T: SendInput<M>
+{
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        T::collect_senders(collector)
+    }
+}
+
+impl<T> SendInput<Forbidden> for Absent<T>
+where
+    T: Truth + 'static
+{
+    fn collect_senders<C>(_: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {}
+}
+
+impl<P> SendInput<Parameter> for Param<P> {
+    fn collect_senders<C>(_: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {}
+}
+
+macro_rules! impl_send_in {
+    ($($T:ident $M:ident),*) => {
+        impl<$($T,)* $($M,)*> SendInput<($($M,)*)> for ($($T,)*)
+        where
+            $($T: SendInput<$M>,)*
+        {
+            #[allow(unused)]
+            fn collect_senders<C>(collector: &mut C)
+            where
+                C: FnMut(Id, Sender)
+            {
+                $(
+                    <$T as SendInput<$M>>::collect_senders(collector);
+                )*
+            }
+        }
+    }
+}
+
+for_each_prefix!(impl_send_in,
+    A1 M1, A2 M2, A3 M3, A4 M4, A5 M5, A6 M6, A7 M7, A8 M8, A9 M9, A10 M10, A11 M11, A12 M12,
+    A13 M13, A14 M14, A15 M15, A16 M16, A17 M17, A18 M18, A19 M19, A20 M20, A21 M21, A22 M22, A23 M23, A24 M24);
+
+/// A transition result that only contains truths which can be sent to other threads.
+pub(crate) trait SendOutput: TransitionOutput {
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender);
+}
+
+impl<T: Truth + Send + 'static> SendOutput for T {
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        collector(Id::of::<T>(), send_truth::<T>)
+    }
+}
+
+impl<A> SendOutput for Option<A>
+where
+    A: SendOutput
+{
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        A::collect_senders(collector)
+    }
+}
+
+impl<A,E> SendOutput for Result<A,E>
+where
+    A: SendOutput,
+    E: Into<Box<dyn Error + Send + Sync>>
+{
+    fn collect_senders<C>(collector: &mut C)
+    where
+        C: FnMut(Id, Sender)
+    {
+        A::collect_senders(collector)
+    }
+}
+
+macro_rules! impl_send_out {
+    ($($T:ident),*) => {
+        impl<$($T,)*> SendOutput for ($($T,)*)
+        where
+            $($T: SendOutput,)*
+        {
+            #[allow(unused)]
+            fn collect_senders<C>(collector: &mut C)
+            where
+                C: FnMut(Id, Sender)
+            {
+                $(
+                    $T::collect_senders(collector);
+                )*
+            }
+        }
+    }
+}
+
+for_each_prefix!(impl_send_out, A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11, A12, A13, A14, A15, A16);
+
+/// A transition that can be shared between threads.
+///
+/// Send transitions are run by a `SendStateMachine` or a `SharedStateMachine`. They can be created from functions that implement `Fn`, `Send` and `Sync`,
+/// and only take and produce truths that implement `Send`, using the `IntoSendTransition` and `IntoSendTransitionParameterized` traits.
+/// Unlike `Transition`, a send transition can not have guards.
+pub struct SendTransition<'a> {
+    pub(crate) func: Box<SendTransitionFn<'a>>,
+    pub(crate) signature: Signature,
+    pub(crate) senders: HashMap<Id, Sender>,
+    pub(crate) priority: i32
+}
+
+impl<'a> SendTransition<'a> {
+    /// Sets the priority of this transition.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Returns the priority of this transition.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns the truths this transition reads from and writes to the state.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    pub(crate) fn run(&self, state: &mut State) -> Result<(), TransitionError> {
+        (self.func)(state)
+    }
+
+    /// Turns a truth that this transition left in the state back into a truth that can be sent to another thread.
+    pub(crate) fn send(&self, id: &Id, truth: Box<dyn Any>) -> Box<dyn Any + Send> {
+        let sender = self.senders.get(id).expect("A send transition left a truth it does not know in the state");
+        sender(truth)
+    }
+
+    /// Every truth this transition can take out of the state, change or insert into the state.
+    pub(crate) fn touches(&self) -> impl Iterator<Item = &Id> {
+        self.senders.keys()
+    }
+
+    /// Checks if this transition can not run at the same time as the other transition.
+    ///
+    /// Two transitions conflict if they can take, change or insert the same truth,
+    /// or if one of them can insert a truth the other one forbids.
+    pub(crate) fn conflicts_with(&self, other: &SendTransition) -> bool {
+        self.touches().any(|id| other.senders.contains_key(id) || other.signature.forbids.contains(id))
+            || other.touches().any(|id| self.signature.forbids.contains(id))
+    }
+}
+
+impl<'a> Debug for SendTransition<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(format!("SendTransition({}->{})", self.signature.requires.len(), self.signature.produces.len()).as_str())
+    }
+}
+
+/// A trait that allows an object to be converted into a `SendTransition` with some parameters.
+///
+/// This trait is implemented for:
+///
+/// - The `SendTransition` type.
+/// - `Fn` types that also implement `Send` and `Sync`, and take up to 24 arguments, of which up to 8 can be parameters.
+///
+/// Inputs and outputs are the same as for `IntoTransitionParameterized`, but every truth they take or produce
+/// has to implement `Send`, and parameters have to implement `Send` and `Sync`.
+pub trait IntoSendTransitionParameterized<'a,In,Param> {
+    /// Converts this object into a `SendTransition` with the given parameters.
+    fn into_send_transition_with(self, params: Param) -> Result<SendTransition<'a>,InvalidTransitionError>;
+}
+
+/// A trait that allows an object to be converted into a `SendTransition`.
+///
+/// See `IntoSendTransitionParameterized` for the objects it is implemented for.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// #[derive(Truth,Debug)]
+/// struct A(i32);
+///
+/// fn increment(a: &mut A) {
+///     a.0 += 1;
+/// }
+///
+/// let transition = increment.into_send_transition().unwrap();
+///
+/// let mut state_machine = SendStateMachine::new();
+/// state_machine.set_truth(A(0));
+/// state_machine.run(&transition).unwrap();
+///
+/// assert_eq!(state_machine.unset_truth::<A>().unwrap().0, 1);
+/// ```
+pub trait IntoSendTransition<'a,In> {
+    fn into_send_transition(self) -> Result<SendTransition<'a>,InvalidTransitionError>;
+}
+
+impl<'a> IntoSendTransitionParameterized<'a, UnknownInput, ()> for SendTransition<'a>
+{
+    fn into_send_transition_with(self, _params: ()) -> Result<SendTransition<'a>,InvalidTransitionError> {
+        Ok(self)
+    }
+}
+
+#[allow(private_bounds)]
+impl<'a,In,M,Param,F> IntoSendTransitionParameterized<'a,(In,M),Param> for F
+where
+    In: SendInput<M>,
+    Param: Clone + Send + Sync + 'a,
+    F: TransitionFunction<In,M,Param> + Send + Sync + 'a,
+    F::Result: SendOutput
+{
+    fn into_send_transition_with(self, params: Param) -> Result<SendTransition<'a>,InvalidTransitionError> {
+        let mut senders = HashMap::new();
+        In::collect_senders(&mut |id, sender| { senders.insert(id, sender); });
+        F::Result::collect_senders(&mut |id, sender| { senders.insert(id, sender); });
+
+        Ok(SendTransition {
+            func: Box::new(move |args| {
+                let mut fetch = In::fetch(args)?;
+                let res = self.call(In::item(&mut fetch), params.clone());
+                In::restore(fetch, args);
+                res.insert_into(args)
+            }),
+            signature: Signature::of::<In,M,F::Result>()?,
+            senders,
+            priority: 0
+        })
+    }
+}
+
+impl<'a,In,F> IntoSendTransition<'a,In> for F
+where
+    F: IntoSendTransitionParameterized<'a, In, ()>
+{
+    fn into_send_transition(self) -> Result<SendTransition<'a>,InvalidTransitionError> {
+        self.into_send_transition_with(())
+    }
+}
@@ -0,0 +1,250 @@
+use std::{any::Any, marker::PhantomData};
+
+use crate::{Id, State, Truth};
+
+use super::TransitionError;
+use super::function::{InputMarker, TransitionInput};
+
+/// A tag that truths can be marked with, by deriving `Truth` with `#[truth(tag = "...")]`.
+///
+/// The name of the tag has to match the name used in the attribute. See `Tagged` for an example.
+pub trait Tag {
+    const NAME: &'static str;
+}
+
+/// Marker for inputs that take every truth marked with a tag.
+pub struct Drained;
+
+impl InputMarker for Drained {}
+
+/// A transition input that takes every truth marked with the tag `G` out of the state, whatever its type.
+///
+/// The input never makes a transition fail: if no tagged truth is in the state, it is empty.
+/// Truths reserved by a pending async transition are not taken.
+/// The taken truths are boxed, and can be read by downcasting them to their concrete types.
+///
+/// The tagged truths are not known when the transition is created, so they are not part of the
+/// `requires`, `optional` or `consumes` sets of its signature. Instead, the tag is listed in `Signature::drains`.
+///
+/// Like the other inputs, the drained truths are put back into the state if the transition fails, and are recorded
+/// in the history when it is enabled. As for truths taken by value, only cloneable truths can be restored once the
+/// transition function consumed them, and the history requires every drained truth to be cloneable.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// struct Pending;
+///
+/// impl Tag for Pending {
+///     const NAME: &'static str = "pending";
+/// }
+///
+/// #[derive(Truth,Debug)]
+/// #[truth(tag = "pending")]
+/// struct Email(&'static str);
+///
+/// #[derive(Truth,Debug)]
+/// #[truth(tag = "pending")]
+/// struct Sms(&'static str);
+///
+/// #[derive(Truth,Debug)]
+/// struct Sent(usize);
+///
+/// fn flush(mut pending: Tagged<Pending>) -> Sent {
+///     if let Some(email) = pending.take::<Email>() {
+///         assert_eq!(email.0, "alice@example.com");
+///     }
+///     Sent(pending.len() + 1)
+/// }
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Email("alice@example.com"));
+/// state_machine.set_truth(Sms("+1 555 0100"));
+///
+/// state_machine.run(flush).unwrap();
+///
+/// assert!(!state_machine.has_truth::<Email>());
+/// assert!(!state_machine.has_truth::<Sms>());
+/// assert_eq!(state_machine.unset_truth::<Sent>().unwrap().0, 2);
+/// ```
+///
+/// Drained truths are restored when the transition is undone, or when it fails:
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// struct Pending;
+///
+/// impl Tag for Pending {
+///     const NAME: &'static str = "pending";
+/// }
+///
+/// #[derive(Truth,Debug,Clone)]
+/// #[truth(clone, tag = "pending")]
+/// struct Email(&'static str);
+///
+/// #[derive(Truth,Debug,Clone)]
+/// #[truth(clone)]
+/// struct Sent(usize);
+///
+/// fn flush(pending: Tagged<Pending>) -> Sent {
+///     Sent(pending.len())
+/// }
+///
+/// fn flush_offline(pending: Tagged<Pending>) -> Result<Sent, String> {
+///     drop(pending);
+///     Err("offline".to_string())
+/// }
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Email("alice@example.com"));
+///
+/// assert!(state_machine.run(flush_offline).is_err());
+/// assert!(state_machine.has_truth::<Email>());
+///
+/// state_machine.enable_history(10);
+/// state_machine.run(flush).unwrap();
+/// assert!(!state_machine.has_truth::<Email>());
+///
+/// state_machine.undo();
+/// assert!(state_machine.has_truth::<Email>());
+/// assert!(!state_machine.has_truth::<Sent>());
+/// ```
+///
+/// A function can not take a truth as an explicit input and drain its tag as well:
+///
+/// ```
+/// use pssm::prelude::*;
+///
+/// struct Pending;
+///
+/// impl Tag for Pending {
+///     const NAME: &'static str = "pending";
+/// }
+///
+/// #[derive(Truth,Debug)]
+/// #[truth(tag = "pending")]
+/// struct Email(&'static str);
+///
+/// fn flush_twice(email: &Email, pending: Tagged<Pending>) {}
+///
+/// let error = flush_twice.into_transition().err().unwrap();
+/// assert!(error.to_string().contains("drained by a tag"));
+/// ```
+pub struct Tagged<G> {
+    truths: Vec<(Id, Box<dyn Any>)>,
+    tag: PhantomData<G>
+}
+
+impl<G> Tagged<G> {
+    /// Returns the number of truths that were taken.
+    pub fn len(&self) -> usize {
+        self.truths.len()
+    }
+
+    /// Checks if no truth was taken.
+    pub fn is_empty(&self) -> bool {
+        self.truths.is_empty()
+    }
+
+    /// Returns the ids of the truths that were taken.
+    pub fn ids(&self) -> impl Iterator<Item = &Id> {
+        self.truths.iter().map(|(id, _)| id)
+    }
+
+    /// Checks if a truth of the given type was taken.
+    pub fn has<T: Truth + 'static>(&self) -> bool {
+        self.truths.iter().any(|(id, _)| *id == Id::of::<T>())
+    }
+
+    /// Returns a reference to the taken truth of the given type, if there is one.
+    pub fn get<T: Truth + 'static>(&self) -> Option<&T> {
+        self.truths.iter()
+            .find(|(id, _)| *id == Id::of::<T>())
+            .map(|(_, truth)| truth.downcast_ref::<T>().expect("Invalid type stored for a truth in the state"))
+    }
+
+    /// Removes the taken truth of the given type and returns it, if there is one.
+    pub fn take<T: Truth + 'static>(&mut self) -> Option<T> {
+        let index = self.truths.iter().position(|(id, _)| *id == Id::of::<T>())?;
+        let (_, truth) = self.truths.remove(index);
+        Some(*truth.downcast::<T>().expect("Invalid type stored for a truth in the state"))
+    }
+
+    /// Returns the ids of the taken truths together with references to them, to be downcast to their types.
+    pub fn iter(&self) -> impl Iterator<Item = (&Id, &dyn Any)> {
+        self.truths.iter().map(|(id, truth)| (id, truth.as_ref()))
+    }
+}
+
+impl<G> IntoIterator for Tagged<G> {
+    type Item = (Id, Box<dyn Any>);
+    type IntoIter = std::vec::IntoIter<(Id, Box<dyn Any>)>;
+
+    /// Returns the ids of the taken truths together with the boxed truths, to be downcast to their types.
+    fn into_iter(self) -> Self::IntoIter {
+        self.truths.into_iter()
+    }
+}
+
+impl<G> TransitionInput<Drained> for Tagged<G>
+where
+    G: Tag
+{
+    type Fetch = Option<Tagged<G>>;
+    type Item<'f> = Tagged<G>;
+
+    fn fetch(state: &mut State) -> Result<Self::Fetch, TransitionError> {
+        let mut ids: Vec<Id> = state.ids()
+            .filter(|id| id.tags().contains(&G::NAME) && !state.is_reserved(id))
+            .copied()
+            .collect();
+        ids.sort();
+
+        let truths = ids.into_iter()
+            .filter_map(|id| state.remove(&id).map(|truth| (id, truth)))
+            .collect();
+        Ok(Some(Tagged { truths, tag: PhantomData }))
+    }
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+        fetch.take().expect("A truth was handed to a transition function twice")
+    }
+
+    fn restore(fetch: Self::Fetch, state: &mut State) {
+        for (id, truth) in fetch.into_iter().flatten() {
+            state.put_back(id, truth);
+        }
+    }
+
+    fn collect_required<C,E>(_: &mut C) -> Result<(),E>
+    where
+        C: FnMut(Id) -> Result<(),E>
+    {
+        Ok(())
+    }
+
+    fn collect_borrowed<C>(_: &mut C)
+    where
+        C: FnMut(Id)
+    {}
+
+    fn collect_optional<C>(_: &mut C)
+    where
+        C: FnMut(Id)
+    {}
+
+    fn collect_forbidden<C>(_: &mut C)
+    where
+        C: FnMut(Id)
+    {}
+
+    fn collect_drained<C>(collector: &mut C)
+    where
+        C: FnMut(&'static str)
+    {
+        collector(G::NAME)
+    }
+}
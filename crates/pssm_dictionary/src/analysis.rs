@@ -0,0 +1,246 @@
+use std::{collections::{BTreeSet, HashSet, VecDeque}, hash::Hash};
+use pssm_core::TruthId;
+
+use crate::{plan::{can_apply, outcomes, Truths}, TransitionDictionary};
+
+/// The truths and transitions of a `TransitionDictionary` that are reachable from a set of truths.
+///
+/// Created by `TransitionDictionary::analyze`.
+#[derive(Debug, Clone)]
+pub struct Analysis<K> {
+    reachable: HashSet<TruthId>,
+    unreachable: HashSet<TruthId>,
+    dead_transitions: Vec<Vec<K>>
+}
+
+impl<K> Analysis<K> {
+    /// The truths that are in the start set or can be produced by some sequence of transitions.
+    pub fn reachable(&self) -> &HashSet<TruthId> {
+        &self.reachable
+    }
+
+    /// The truths that are used by some transition, but can never be in the state.
+    pub fn unreachable(&self) -> &HashSet<TruthId> {
+        &self.unreachable
+    }
+
+    /// The key paths of the transitions that can never run.
+    pub fn dead_transitions(&self) -> &[Vec<K>] {
+        &self.dead_transitions
+    }
+
+    /// Checks if the given truth can be in the state.
+    pub fn is_reachable(&self, id: &TruthId) -> bool {
+        self.reachable.contains(id)
+    }
+}
+
+/// The sets of truths a state can contain, found by `TransitionDictionary::reachable_states`.
+#[derive(Debug, Clone)]
+pub struct StateSpace {
+    states: Vec<BTreeSet<TruthId>>,
+    complete: bool
+}
+
+impl StateSpace {
+    /// The sets of truths that were found, in the order they were found.
+    pub fn states(&self) -> &[BTreeSet<TruthId>] {
+        &self.states
+    }
+
+    /// Checks if every reachable set of truths was found, or if the enumeration stopped at the bound.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+}
+
+impl<'a,K: Hash + Eq + Clone> TransitionDictionary<'a,K> {
+    /// Computes which truths can be in the state and which transitions can run, starting from the given truths.
+    ///
+    /// This function only uses the truths the transitions in this dictionary and all of its folders require and produce.
+    /// Truths a transition may produce, like the truths of an `Option` output, count as produced. A transition is assumed to be able to run once all of the truths it requires, and one truth of each of its alternatives,
+    /// are reachable, so a transition that is reported as dead can never run, and a truth that is reported as unreachable can never be in the state.
+    /// Truths that transitions forbid or take out of the state, and their guards, are not taken into account,
+    /// so not every transition that is not reported as dead can actually run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Order();
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Paid();
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Refund();
+    ///
+    /// fn pay(_: &Order) -> Paid {
+    ///     Paid()
+    /// }
+    ///
+    /// fn refund(_: Paid, _: Refund) {}
+    ///
+    /// fn close(_: Either<Refund, Paid>) {}
+    ///
+    /// let mut transitions = TransitionDictionary::new();
+    /// transitions.add_transition("pay", pay).unwrap();
+    /// transitions.add_transition("refund", refund).unwrap();
+    /// transitions.add_transition("close", close).unwrap();
+    ///
+    /// let analysis = transitions.analyze([TruthId::of::<Order>()]);
+    ///
+    /// assert!(analysis.is_reachable(&TruthId::of::<Paid>()));
+    /// assert!(analysis.unreachable().contains(&TruthId::of::<Refund>()));
+    /// assert_eq!(analysis.dead_transitions(), &[vec!["refund"]]);
+    /// ```
+    ///
+    /// A truth that is returned in an `Option` is reachable, and so are the transitions that require it.
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Order(u32);
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Paid();
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Shipped();
+    ///
+    /// fn pay(order: &Order) -> Option<Paid> {
+    ///     (order.0 > 0).then_some(Paid())
+    /// }
+    ///
+    /// fn ship(_: Paid) -> Shipped {
+    ///     Shipped()
+    /// }
+    ///
+    /// let mut transitions = TransitionDictionary::new();
+    /// transitions.add_transition("pay", pay).unwrap();
+    /// transitions.add_transition("ship", ship).unwrap();
+    ///
+    /// let analysis = transitions.analyze([TruthId::of::<Order>()]);
+    ///
+    /// assert!(analysis.is_reachable(&TruthId::of::<Shipped>()));
+    /// assert!(analysis.dead_transitions().is_empty());
+    /// ```
+    pub fn analyze<S>(&self, start: S) -> Analysis<K>
+    where
+        S: IntoIterator<Item = TruthId>
+    {
+        let transitions = self.iter_deep();
+        let mut reachable: HashSet<TruthId> = start.into_iter().collect();
+        let mut fired = vec![false; transitions.len()];
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for ((_, transition), fired) in transitions.iter().zip(fired.iter_mut()) {
+                let signature = transition.signature();
+                if *fired
+                    || !signature.requires().is_subset(&reachable)
+                    || !signature.alternatives().iter().all(|ids| ids.iter().any(|id| reachable.contains(id))) {
+                    continue;
+                }
+
+                *fired = true;
+                changed = true;
+                reachable.extend(signature.produces());
+                reachable.extend(signature.may_produce());
+            }
+        }
+
+        let unreachable = transitions.iter()
+            .flat_map(|(_, transition)| {
+                let signature = transition.signature();
+                signature.requires().iter()
+                    .chain(signature.optional())
+                    .chain(signature.alternatives().iter().flatten())
+                    .chain(signature.forbids())
+                    .chain(signature.produces())
+                    .chain(signature.may_produce())
+            })
+            .filter(|id| !reachable.contains(id))
+            .copied()
+            .collect();
+
+        let dead_transitions = transitions.into_iter()
+            .zip(fired)
+            .filter(|(_, fired)| !fired)
+            .map(|((path, _), _)| path)
+            .collect();
+
+        Analysis { reachable, unreachable, dead_transitions }
+    }
+
+    /// Enumerates the sets of truths a state can contain, starting from the given truths.
+    ///
+    /// This function will do a breadth-first search over the truths the transitions in this dictionary and all of its
+    /// folders require, forbid, take and produce, like `plan`, following every combination of the truths a transition may produce. At most `bound` sets of truths are enumerated,
+    /// `StateSpace::is_complete` tells if every reachable set was found.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Light();
+    ///
+    /// fn switch_on(_: Absent<Light>) -> Light {
+    ///     Light()
+    /// }
+    ///
+    /// fn switch_off(_: Light) {}
+    ///
+    /// let mut transitions = TransitionDictionary::new();
+    /// transitions.add_transition("on", switch_on).unwrap();
+    /// transitions.add_transition("off", switch_off).unwrap();
+    ///
+    /// let states = transitions.reachable_states([], 10);
+    /// assert!(states.is_complete());
+    /// assert_eq!(states.states().len(), 2);
+    ///
+    /// let states = transitions.reachable_states([], 1);
+    /// assert!(!states.is_complete());
+    /// ```
+    pub fn reachable_states<S>(&self, start: S, bound: usize) -> StateSpace
+    where
+        S: IntoIterator<Item = TruthId>
+    {
+        let transitions = self.iter_deep();
+        let start: Truths = start.into_iter().collect();
+
+        let mut states = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        seen.insert(start.clone());
+        queue.push_back(start);
+
+        while let Some(truths) = queue.pop_front() {
+            if states.len() == bound {
+                return StateSpace { states, complete: false };
+            }
+
+            for (_, transition) in &transitions {
+                let signature = transition.signature();
+                if !can_apply(signature, &truths) {
+                    continue;
+                }
+                for next in outcomes(signature, &truths) {
+                    if seen.insert(next.clone()) {
+                        queue.push_back(next);
+                    }
+                }
+            }
+
+            states.push(truths);
+        }
+
+        StateSpace { states, complete: true }
+    }
+}
@@ -557,4 +557,46 @@ impl<K: Hash + Eq, V> Dictionary<K, V> {
     pub fn into_inner(self) -> (HashMap<K, V>, HashMap<K, Dictionary<K, V>>) {
         (self.entries, self.folders)
     }
-}
\ No newline at end of file
+}
+
+impl<K: Hash + Eq, V> Default for Dictionary<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Dictionary<K, V> {
+    /// Returns the values stored in the dictionary and all of its sub-dictionaries, together with their key paths.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pssm_dictionary::Dictionary;
+    ///
+    /// let mut dict = Dictionary::new();
+    /// dict.insert("key", 5);
+    ///
+    /// let mut folder = Dictionary::new();
+    /// folder.insert("key", 10);
+    /// dict.insert_folder("folder", folder);
+    ///
+    /// let mut values = dict.iter_deep();
+    /// values.sort();
+    ///
+    /// assert_eq!(values, vec![(vec!["folder", "key"], &10), (vec!["key"], &5)]);
+    /// ```
+    pub fn iter_deep(&self) -> Vec<(Vec<K>, &V)> {
+        let mut values: Vec<_> = self.entries.iter()
+            .map(|(key, value)| (vec![key.clone()], value))
+            .collect();
+
+        for (key, folder) in &self.folders {
+            values.extend(folder.iter_deep().into_iter().map(|(mut path, value)| {
+                path.insert(0, key.clone());
+                (path, value)
+            }));
+        }
+
+        values
+    }
+}
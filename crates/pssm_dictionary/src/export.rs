@@ -0,0 +1,326 @@
+use std::{collections::HashMap, fmt::Display, hash::Hash};
+use pssm_core::{transition::{Signature, TransitionMut}, TruthId};
+
+use crate::TransitionDictionary;
+
+/// How a transition uses a truth, drawn as an edge between the truth and the transition.
+#[derive(Clone, Copy)]
+enum Edge {
+    Consumes,
+    Borrows,
+    Optional,
+    Alternative,
+    Forbids,
+    Produces,
+    MayProduce
+}
+
+impl Edge {
+    fn of(signature: &Signature) -> impl Iterator<Item = (Edge, &TruthId)> {
+        signature.requires().iter()
+            .map(|id| (if signature.borrows().contains(id) { Edge::Borrows } else { Edge::Consumes }, id))
+            .chain(signature.optional().iter().map(|id| (Edge::Optional, id)))
+            .chain(signature.alternatives().iter().flatten().map(|id| (Edge::Alternative, id)))
+            .chain(signature.forbids().iter().map(|id| (Edge::Forbids, id)))
+            .chain(signature.produces().iter().map(|id| (Edge::Produces, id)))
+            .chain(signature.may_produce().iter().map(|id| (Edge::MayProduce, id)))
+    }
+}
+
+/// The nodes of the graph of a `TransitionDictionary`, numbered in the order they are written.
+struct Graph {
+    truths: Vec<TruthId>,
+    indices: HashMap<TruthId, usize>,
+    tags: Vec<&'static str>,
+    tag_indices: HashMap<&'static str, usize>,
+    transitions: usize,
+    clusters: usize
+}
+
+impl Graph {
+    fn new<K: Hash + Eq>(dictionary: &TransitionDictionary<'_,K>) -> Self {
+        let mut truths = Vec::new();
+        let mut tags = Vec::new();
+        collect_truths(dictionary, &mut truths, &mut tags);
+        truths.sort_by(|a, b| a.name().cmp(b.name()).then(a.cmp(b)));
+        truths.dedup();
+        tags.sort();
+        tags.dedup();
+
+        let indices = truths.iter().enumerate().map(|(index, id)| (*id, index)).collect();
+        let tag_indices = tags.iter().enumerate().map(|(index, tag)| (*tag, index)).collect();
+        Self { truths, indices, tags, tag_indices, transitions: 0, clusters: 0 }
+    }
+
+    /// The tags drained by a transition, ordered like the tag nodes.
+    fn drains(&self, signature: &Signature) -> Vec<usize> {
+        let mut drains: Vec<_> = signature.drains().iter().map(|tag| self.tag_indices[tag]).collect();
+        drains.sort();
+        drains
+    }
+
+    /// The truths of the graph marked with a drained tag, as pairs of truth and tag nodes.
+    fn tagged(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.truths.iter().enumerate().flat_map(move |(truth, id)| {
+            id.tags().iter().filter_map(move |tag| self.tag_indices.get(tag).map(|tag| (truth, *tag)))
+        })
+    }
+
+    /// The edges of a transition, ordered by the truths they connect it to.
+    fn edges(&self, signature: &Signature) -> Vec<(Edge, usize)> {
+        let mut edges: Vec<_> = Edge::of(signature).map(|(edge, id)| (edge, self.indices[id])).collect();
+        edges.sort_by_key(|(_, index)| *index);
+        edges
+    }
+}
+
+fn collect_truths<K: Hash + Eq>(dictionary: &TransitionDictionary<'_,K>, truths: &mut Vec<TruthId>, tags: &mut Vec<&'static str>) {
+    for (_, transition) in dictionary.iter() {
+        truths.extend(Edge::of(transition.signature()).map(|(_, id)| *id));
+        tags.extend(transition.signature().drains().iter().copied());
+    }
+    for (_, folder) in dictionary.iter_folders() {
+        collect_truths(folder, truths, tags);
+    }
+}
+
+/// The entries and folders of a dictionary, keyed by their labels.
+type Sorted<'d,'a,K> = (Vec<(String, &'d TransitionMut<'a>)>, Vec<(String, &'d TransitionDictionary<'a,K>)>);
+
+/// The entries and folders of a dictionary, ordered by their keys.
+fn sorted<'d,'a,K: Hash + Eq + Display>(dictionary: &'d TransitionDictionary<'a,K>) -> Sorted<'d,'a,K> {
+    let mut entries: Vec<_> = dictionary.iter().map(|(key, transition)| (key.to_string(), transition)).collect();
+    let mut folders: Vec<_> = dictionary.iter_folders().map(|(key, folder)| (key.to_string(), folder)).collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    folders.sort_by(|a, b| a.0.cmp(&b.0));
+    (entries, folders)
+}
+
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_mermaid(label: &str) -> String {
+    label.replace('"', "#quot;")
+}
+
+fn write_dot<K: Hash + Eq + Display>(dictionary: &TransitionDictionary<'_,K>, graph: &mut Graph, indent: &str, out: &mut String, edges: &mut String) {
+    let (entries, folders) = sorted(dictionary);
+
+    for (key, transition) in entries {
+        let node = graph.transitions;
+        graph.transitions += 1;
+        out.push_str(&format!("{}e{} [label=\"{}\", shape=box];\n", indent, node, escape_dot(&key)));
+
+        for (edge, truth) in graph.edges(transition.signature()) {
+            edges.push_str(&match edge {
+                Edge::Consumes => format!("    t{} -> e{};\n", truth, node),
+                Edge::Borrows => format!("    t{} -> e{} [style=dashed];\n", truth, node),
+                Edge::Optional => format!("    t{} -> e{} [style=dotted];\n", truth, node),
+                Edge::Alternative => format!("    t{} -> e{} [style=bold];\n", truth, node),
+                Edge::Forbids => format!("    t{} -> e{} [arrowhead=tee];\n", truth, node),
+                Edge::Produces => format!("    e{} -> t{};\n", node, truth),
+                Edge::MayProduce => format!("    e{} -> t{} [style=dashed];\n", node, truth)
+            });
+        }
+        for tag in graph.drains(transition.signature()) {
+            edges.push_str(&format!("    g{} -> e{} [label=\"drains\"];\n", tag, node));
+        }
+    }
+
+    for (key, folder) in folders {
+        let cluster = graph.clusters;
+        graph.clusters += 1;
+        out.push_str(&format!("{}subgraph cluster_{} {{\n{}    label=\"{}\";\n", indent, cluster, indent, escape_dot(&key)));
+        write_dot(folder, graph, &format!("{}    ", indent), out, edges);
+        out.push_str(&format!("{}}}\n", indent));
+    }
+}
+
+fn write_mermaid<K: Hash + Eq + Display>(dictionary: &TransitionDictionary<'_,K>, graph: &mut Graph, indent: &str, out: &mut String, edges: &mut String) {
+    let (entries, folders) = sorted(dictionary);
+
+    for (key, transition) in entries {
+        let node = graph.transitions;
+        graph.transitions += 1;
+        out.push_str(&format!("{}e{}[\"{}\"]\n", indent, node, escape_mermaid(&key)));
+
+        for (edge, truth) in graph.edges(transition.signature()) {
+            edges.push_str(&match edge {
+                Edge::Consumes => format!("    t{} --> e{}\n", truth, node),
+                Edge::Borrows => format!("    t{} -.-> e{}\n", truth, node),
+                Edge::Optional => format!("    t{} -. optional .-> e{}\n", truth, node),
+                Edge::Alternative => format!("    t{} == one of ==> e{}\n", truth, node),
+                Edge::Forbids => format!("    t{} --x e{}\n", truth, node),
+                Edge::Produces => format!("    e{} --> t{}\n", node, truth),
+                Edge::MayProduce => format!("    e{} -.-> t{}\n", node, truth)
+            });
+        }
+        for tag in graph.drains(transition.signature()) {
+            edges.push_str(&format!("    g{} -- drains --> e{}\n", tag, node));
+        }
+    }
+
+    for (key, folder) in folders {
+        let cluster = graph.clusters;
+        graph.clusters += 1;
+        out.push_str(&format!("{}subgraph c{} [\"{}\"]\n", indent, cluster, escape_mermaid(&key)));
+        write_mermaid(folder, graph, &format!("{}    ", indent), out, edges);
+        out.push_str(&format!("{}end\n", indent));
+    }
+}
+
+impl<'a,K: Hash + Eq + Display> TransitionDictionary<'a,K> {
+    /// Renders the transitions in this dictionary as a Graphviz DOT graph.
+    ///
+    /// Truths are drawn as ellipses labeled with their names, transitions as boxes labeled with their keys.
+    /// Every transition is connected to the truths it uses: a solid edge for truths it takes, a dashed edge for truths it borrows,
+    /// a dotted edge for truths it takes if they are there, a bold edge for alternative truths of which it uses the first one there,
+    /// an edge ending in a bar for truths it forbids, an edge to every truth it produces, and a dashed edge to every truth it may produce.
+    ///
+    /// Tags drained by `Tagged` inputs are drawn as hexagons, with an edge labeled "drains" to the transitions draining them.
+    /// The truths of the graph marked with a drained tag are linked to it by a dotted line. Folders are drawn as clusters.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Order();
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Shipped();
+    ///
+    /// fn ship(_: Order) -> Shipped {
+    ///     Shipped()
+    /// }
+    ///
+    /// let mut shipping = TransitionDictionary::new();
+    /// shipping.add_transition("ship", ship).unwrap();
+    ///
+    /// let mut transitions = TransitionDictionary::new();
+    /// transitions.insert_folder("shipping", shipping);
+    ///
+    /// let dot = transitions.to_dot();
+    ///
+    /// assert!(dot.starts_with("digraph {\n"));
+    /// assert!(dot.contains(&format!("t0 [label=\"{}\", shape=ellipse];", Order::name())));
+    /// assert!(dot.contains("subgraph cluster_0 {\n        label=\"shipping\";\n        e0 [label=\"ship\", shape=box];\n    }"));
+    /// assert!(dot.contains("t0 -> e0;"));
+    /// assert!(dot.contains("e0 -> t1;"));
+    /// ```
+    ///
+    /// Optional outputs and drained tags:
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    ///
+    /// struct Pending;
+    ///
+    /// impl Tag for Pending {
+    ///     const NAME: &'static str = "pending";
+    /// }
+    ///
+    /// #[derive(Debug,Truth)]
+    /// #[truth(tag = "pending")]
+    /// struct Order();
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Paid();
+    ///
+    /// fn pay(_: &Order) -> Option<Paid> {
+    ///     Some(Paid())
+    /// }
+    ///
+    /// fn cancel_all(_: Tagged<Pending>) {}
+    ///
+    /// let mut transitions = TransitionDictionary::new();
+    /// transitions.add_transition("cancel_all", cancel_all).unwrap();
+    /// transitions.add_transition("pay", pay).unwrap();
+    ///
+    /// let dot = transitions.to_dot();
+    ///
+    /// assert!(dot.contains("g0 [label=\"pending\", shape=hexagon];"));
+    /// assert!(dot.contains("t0 -> e1 [style=dashed];"));
+    /// assert!(dot.contains("e1 -> t1 [style=dashed];"));
+    /// assert!(dot.contains("g0 -> e0 [label=\"drains\"];"));
+    /// assert!(dot.contains("t0 -> g0 [style=dotted, arrowhead=none];"));
+    ///
+    /// let mermaid = transitions.to_mermaid();
+    ///
+    /// assert!(mermaid.contains("g0{{\"pending\"}}"));
+    /// assert!(mermaid.contains("e1 -.-> t1"));
+    /// assert!(mermaid.contains("g0 -- drains --> e0"));
+    /// ```
+    pub fn to_dot(&self) -> String {
+        let mut graph = Graph::new(self);
+        let mut out = String::from("digraph {\n");
+        let mut edges = String::new();
+
+        for (index, id) in graph.truths.iter().enumerate() {
+            out.push_str(&format!("    t{} [label=\"{}\", shape=ellipse];\n", index, escape_dot(id.name())));
+        }
+        for (index, tag) in graph.tags.iter().enumerate() {
+            out.push_str(&format!("    g{} [label=\"{}\", shape=hexagon];\n", index, escape_dot(tag)));
+        }
+        for (truth, tag) in graph.tagged() {
+            edges.push_str(&format!("    t{} -> g{} [style=dotted, arrowhead=none];\n", truth, tag));
+        }
+        write_dot(self, &mut graph, "    ", &mut out, &mut edges);
+
+        out.push_str(&edges);
+        out.push_str("}\n");
+        out
+    }
+
+    /// Renders the transitions in this dictionary as a Mermaid flowchart.
+    ///
+    /// The graph is drawn like in `to_dot`, with folders as subgraphs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Order();
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Paid();
+    ///
+    /// fn pay(_: &Order) -> Paid {
+    ///     Paid()
+    /// }
+    ///
+    /// let mut transitions = TransitionDictionary::new();
+    /// transitions.add_transition("pay", pay).unwrap();
+    ///
+    /// let mermaid = transitions.to_mermaid();
+    ///
+    /// assert!(mermaid.starts_with("flowchart LR\n"));
+    /// assert!(mermaid.contains(&format!("t0([\"{}\"])", Order::name())));
+    /// assert!(mermaid.contains("e0[\"pay\"]"));
+    /// assert!(mermaid.contains("t0 -.-> e0"));
+    /// assert!(mermaid.contains("e0 --> t1"));
+    /// ```
+    pub fn to_mermaid(&self) -> String {
+        let mut graph = Graph::new(self);
+        let mut out = String::from("flowchart LR\n");
+        let mut edges = String::new();
+
+        for (index, id) in graph.truths.iter().enumerate() {
+            out.push_str(&format!("    t{}([\"{}\"])\n", index, escape_mermaid(id.name())));
+        }
+        for (index, tag) in graph.tags.iter().enumerate() {
+            out.push_str(&format!("    g{}{{{{\"{}\"}}}}\n", index, escape_mermaid(tag)));
+        }
+        for (truth, tag) in graph.tagged() {
+            edges.push_str(&format!("    t{} -.- g{}\n", truth, tag));
+        }
+        write_mermaid(self, &mut graph, "    ", &mut out, &mut edges);
+
+        out.push_str(&edges);
+        out
+    }
+}
@@ -0,0 +1,531 @@
+use std::{error::Error, fmt::{Debug, Display}, hash::Hash, marker::PhantomData};
+
+use pssm_core::{transition::{IntoTransitionOnceParameterized, TransitionOnce}, StateMachine, TransitionCallError, TruthRegistry};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{Dictionary, TransitionDictionary};
+
+type BuildFn<'a> = dyn Fn(&[u8]) -> Result<TransitionOnce<'a>, JournalError> + 'a;
+type ValuesFn = fn(&StateMachine, &TruthRegistry) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+
+/// A serde format that the parameters of the transitions in a `ParameterizedDictionary` are stored in.
+///
+/// A `Journal` records the parameters as bytes in this format, so the journal itself can be serialized with any serde format.
+///
+/// # Examples
+///
+/// ```
+/// use std::error::Error;
+/// use serde::{de::DeserializeOwned, Serialize};
+/// use pssm::dictionary::ParamFormat;
+///
+/// struct Json;
+///
+/// impl ParamFormat for Json {
+///     fn to_bytes<P: Serialize>(params: &P) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+///         Ok(serde_json::to_vec(params)?)
+///     }
+///
+///     fn from_bytes<P: DeserializeOwned>(bytes: &[u8]) -> Result<P, Box<dyn Error + Send + Sync>> {
+///         Ok(serde_json::from_slice(bytes)?)
+///     }
+/// }
+///
+/// let bytes = Json::to_bytes(&(3u32, "a")).unwrap();
+/// assert_eq!(Json::from_bytes::<(u32, String)>(&bytes).unwrap(), (3, "a".to_string()));
+/// ```
+pub trait ParamFormat {
+    /// Serializes the given parameters.
+    fn to_bytes<P: Serialize>(params: &P) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+
+    /// Deserializes parameters from the given bytes.
+    fn from_bytes<P: DeserializeOwned>(bytes: &[u8]) -> Result<P, Box<dyn Error + Send + Sync>>;
+}
+
+/// A transition that takes its parameters from their serialized form, in the format `F`.
+///
+/// Parameterized transitions are stored in a `ParameterizedDictionary`, so that a `Journal` can record
+/// the parameters they were run with, and run them with the same parameters again when it is replayed.
+pub struct ParameterizedTransition<'a,F> {
+    build: Box<BuildFn<'a>>,
+    format: PhantomData<fn() -> F>
+}
+
+impl<'a,F: ParamFormat> ParameterizedTransition<'a,F> {
+    /// Creates a parameterized transition from a function that takes parameters.
+    ///
+    /// The function is cloned every time the transition is run, and its parameters are deserialized into `Param`.
+    pub fn new<T,In,Param>(transition: T) -> Self
+    where
+        T: IntoTransitionOnceParameterized<'a,In,Param> + Clone + 'a,
+        Param: DeserializeOwned
+    {
+        Self {
+            build: Box::new(move |params| {
+                let params = F::from_bytes(params).map_err(|e| JournalError::Params(ParamsError(e)))?;
+                transition.clone().into_transition_once_with(params)
+                    .map_err(|e| JournalError::Transition(e.into()))
+            }),
+            format: PhantomData
+        }
+    }
+
+    /// Creates a transition with the given serialized parameters.
+    fn build(&self, params: &[u8]) -> Result<TransitionOnce<'a>, JournalError> {
+        (self.build)(params)
+    }
+}
+
+impl<F> Debug for ParameterizedTransition<'_,F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ParameterizedTransition")
+    }
+}
+
+/// A dictionary of transitions that take parameters, stored in the format `F`.
+///
+/// This dictionary is only available with the `serde` feature. See `Journal` for an example.
+pub type ParameterizedDictionary<'a,K,F> = Dictionary<K, ParameterizedTransition<'a,F>>;
+
+impl<'a,K: Hash + Eq, F: ParamFormat> ParameterizedDictionary<'a,K,F> {
+    /// Adds a transition that takes parameters to this dictionary.
+    ///
+    /// If a transition with the given key already exists in this dictionary, it will be replaced with the new
+    /// transition. Returns the old transition if it exists.
+    pub fn add_parameterized<T,In,Param>(&mut self, key: K, transition: T) -> Option<ParameterizedTransition<'a,F>>
+    where
+        T: IntoTransitionOnceParameterized<'a,In,Param> + Clone + 'a,
+        Param: DeserializeOwned
+    {
+        self.insert(key, ParameterizedTransition::new(transition))
+    }
+}
+
+/// An error of a `ParamFormat`, when parameters or truths could not be serialized or deserialized.
+#[derive(Debug)]
+pub struct ParamsError(Box<dyn Error + Send + Sync>);
+
+impl Display for ParamsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl Error for ParamsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// An error that can occur when a transition is run through a `Journal`.
+#[derive(Debug)]
+pub enum JournalError {
+    /// No transition is stored at the key path.
+    UnknownTransition,
+    /// The parameters could not be serialized, or not be deserialized into the parameters of the transition.
+    Params(ParamsError),
+    /// The truths in the state could not be serialized to record their values.
+    Values(ParamsError),
+    /// The transition could not be run, or failed.
+    Transition(TransitionCallError)
+}
+
+impl Display for JournalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JournalError::UnknownTransition => f.write_str("no transition is stored at the key path"),
+            JournalError::Params(e) => write!(f, "invalid parameters: {}", e),
+            JournalError::Values(e) => write!(f, "truths could not be recorded: {}", e),
+            JournalError::Transition(e) => Display::fmt(e, f)
+        }
+    }
+}
+
+impl Error for JournalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            JournalError::UnknownTransition => None,
+            JournalError::Params(e) | JournalError::Values(e) => Some(e),
+            JournalError::Transition(e) => Some(e)
+        }
+    }
+}
+
+/// A transition that was run through a `Journal`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry<K> {
+    path: Vec<K>,
+    params: Option<Vec<u8>>,
+    succeeded: bool,
+    truths: Vec<String>,
+    values: Option<Vec<u8>>
+}
+
+impl<K> JournalEntry<K> {
+    /// The key path of the transition.
+    pub fn path(&self) -> &[K] {
+        &self.path
+    }
+
+    /// The parameters of the transition, serialized in the `ParamFormat` of the `ParameterizedDictionary` it was taken from.
+    pub fn params(&self) -> Option<&[u8]> {
+        self.params.as_deref()
+    }
+
+    /// Checks if the transition was run successfully.
+    pub fn succeeded(&self) -> bool {
+        self.succeeded
+    }
+
+    /// The sorted names of the truths in the state after the transition was run.
+    pub fn truths(&self) -> &[String] {
+        &self.truths
+    }
+
+    /// The truths in the state after the transition was run, serialized through the `TruthRegistry` of the journal.
+    ///
+    /// Only recorded by journals created with `Journal::with_values`.
+    pub fn values(&self) -> Option<&[u8]> {
+        self.values.as_deref()
+    }
+}
+
+/// How a replayed transition diverged from the one recorded in the journal.
+#[derive(Debug)]
+pub enum DivergenceKind {
+    /// The transition succeeded when it was recorded, but could not be replayed or failed.
+    Failed(JournalError),
+    /// The transition failed when it was recorded, but succeeded when it was replayed.
+    Succeeded,
+    /// The state contains different truths than it did when the transition was recorded.
+    Truths {
+        expected: Vec<String>,
+        actual: Vec<String>
+    },
+    /// The truths in the state have different values than they had when the transition was recorded.
+    Values {
+        expected: Vec<u8>,
+        actual: Vec<u8>
+    }
+}
+
+/// The first transition of a `Journal` whose replay diverged from the recorded run.
+#[derive(Debug)]
+pub struct Divergence<K> {
+    index: usize,
+    path: Vec<K>,
+    kind: DivergenceKind
+}
+
+impl<K> Divergence<K> {
+    /// The index of the diverging entry in the journal.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The key path of the diverging transition.
+    pub fn path(&self) -> &[K] {
+        &self.path
+    }
+
+    /// How the transition diverged.
+    pub fn kind(&self) -> &DivergenceKind {
+        &self.kind
+    }
+}
+
+/// A record of the transitions run on a state machine, that can be replayed to reproduce the run.
+///
+/// Transitions are run through the journal by their key path, either from a `TransitionDictionary` with `run`,
+/// or from a `ParameterizedDictionary` with `run_with`, which also records the serialized parameters.
+/// Every entry records whether the transition succeeded, and the names of the truths in the state afterwards.
+/// A journal created with `with_values` records the values of these truths as well, serialized through a `TruthRegistry`.
+///
+/// `replay` runs the recorded transitions again on another state machine, and reports the first entry
+/// whose outcome or resulting truths differ from the recorded run. Without values, only the names of the truths
+/// are compared, so a truth with a different value is not reported. Truths that were set on the state machine directly
+/// are not recorded, so the state machine passed to `replay` has to start with the same truths as the recorded one.
+///
+/// The journal can be serialized with any serde format, to replay it in another process. The parameters are recorded
+/// in the `ParamFormat` of the `ParameterizedDictionary`. It is only available with the `serde` feature.
+///
+/// # Examples
+///
+/// ```
+/// use std::error::Error;
+/// use serde::{de::DeserializeOwned, Serialize};
+/// use pssm::prelude::*;
+/// use pssm::dictionary::{DivergenceKind, Journal, ParamFormat, ParameterizedDictionary};
+///
+/// struct Json;
+///
+/// impl ParamFormat for Json {
+///     fn to_bytes<P: Serialize>(params: &P) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+///         Ok(serde_json::to_vec(params)?)
+///     }
+///
+///     fn from_bytes<P: DeserializeOwned>(bytes: &[u8]) -> Result<P, Box<dyn Error + Send + Sync>> {
+///         Ok(serde_json::from_slice(bytes)?)
+///     }
+/// }
+///
+/// #[derive(Debug,Truth)]
+/// struct Cart(u32);
+///
+/// #[derive(Debug,Truth)]
+/// struct Order(u32);
+///
+/// fn open() -> Cart {
+///     Cart(0)
+/// }
+///
+/// fn add(cart: &mut Cart, amount: Param<u32>) {
+///     cart.0 += amount.0;
+/// }
+///
+/// fn checkout(cart: Cart) -> Order {
+///     Order(cart.0)
+/// }
+///
+/// let mut transitions = TransitionDictionary::new();
+/// transitions.add_transition("open", open).unwrap();
+/// transitions.add_transition("checkout", checkout).unwrap();
+///
+/// let mut parameterized: ParameterizedDictionary<_,Json> = ParameterizedDictionary::new();
+/// parameterized.add_parameterized("add", add);
+///
+/// let mut state_machine = StateMachine::new();
+/// let mut journal = Journal::new();
+/// journal.run(&mut transitions, &mut state_machine, &["open"]).unwrap();
+/// journal.run_with(&parameterized, &mut state_machine, &["add"], (3u32,)).unwrap();
+/// journal.run_with(&parameterized, &mut state_machine, &["add"], (4u32,)).unwrap();
+/// journal.run(&mut transitions, &mut state_machine, &["checkout"]).unwrap();
+///
+/// let json = serde_json::to_string(&journal).unwrap();
+/// let journal: Journal<&str> = serde_json::from_str(&json).unwrap();
+///
+/// let mut replayed = StateMachine::new();
+/// journal.replay(&mut transitions, &parameterized, &mut replayed).unwrap();
+/// assert_eq!(replayed.unset_truth::<Order>().unwrap().0, 7);
+///
+/// // Replaying on a state machine that already has an order diverges at the first entry.
+/// let mut replayed = StateMachine::new();
+/// replayed.set_truth(Order(1));
+/// let divergence = journal.replay(&mut transitions, &parameterized, &mut replayed).unwrap_err();
+/// assert_eq!(divergence.index(), 0);
+/// assert!(matches!(divergence.kind(), DivergenceKind::Truths { .. }));
+/// ```
+///
+/// Recording the values of the truths detects replays that end with the same truths, but different values:
+///
+/// ```
+/// use std::error::Error;
+/// use serde::{de::DeserializeOwned, Deserialize, Serialize};
+/// use pssm::prelude::*;
+/// use pssm::dictionary::{DivergenceKind, Journal, ParamFormat, ParameterizedDictionary};
+///
+/// struct Json;
+///
+/// impl ParamFormat for Json {
+///     fn to_bytes<P: Serialize>(params: &P) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+///         Ok(serde_json::to_vec(params)?)
+///     }
+///
+///     fn from_bytes<P: DeserializeOwned>(bytes: &[u8]) -> Result<P, Box<dyn Error + Send + Sync>> {
+///         Ok(serde_json::from_slice(bytes)?)
+///     }
+/// }
+///
+/// #[derive(Debug,Truth,Serialize,Deserialize)]
+/// struct Cart(u32);
+///
+/// fn add(cart: &mut Cart, amount: Param<u32>) {
+///     cart.0 += amount.0;
+/// }
+///
+/// fn add_one_more(cart: &mut Cart, amount: Param<u32>) {
+///     cart.0 += amount.0 + 1;
+/// }
+///
+/// let mut registry = TruthRegistry::new();
+/// registry.register::<Cart>("cart").unwrap();
+///
+/// let mut transitions = TransitionDictionary::new();
+///
+/// let mut parameterized: ParameterizedDictionary<_,Json> = ParameterizedDictionary::new();
+/// parameterized.add_parameterized("add", add);
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Cart(0));
+/// let mut journal = Journal::with_values::<Json>(&registry);
+/// journal.run_with(&parameterized, &mut state_machine, &["add"], (7u32,)).unwrap();
+/// assert_eq!(journal.entries()[0].values(), Some(&br#"{"cart":7}"#[..]));
+///
+/// let mut changed: ParameterizedDictionary<_,Json> = ParameterizedDictionary::new();
+/// changed.add_parameterized("add", add_one_more);
+///
+/// let mut replayed = StateMachine::new();
+/// replayed.set_truth(Cart(0));
+/// let divergence = journal.replay(&mut transitions, &changed, &mut replayed).unwrap_err();
+/// assert_eq!(divergence.index(), 0);
+/// assert!(matches!(divergence.kind(), DivergenceKind::Values { .. }));
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Journal<'r,K> {
+    entries: Vec<JournalEntry<K>>,
+    #[serde(skip)]
+    values: Option<Values<'r>>
+}
+
+/// Serializes the truths in a state machine through a `TruthRegistry`, to record their values.
+#[derive(Debug, Clone, Copy)]
+struct Values<'r> {
+    registry: &'r TruthRegistry,
+    serialize: ValuesFn
+}
+
+impl Values<'_> {
+    fn of(&self, state: &StateMachine) -> Result<Vec<u8>, JournalError> {
+        (self.serialize)(state, self.registry).map_err(|e| JournalError::Values(ParamsError(e)))
+    }
+}
+
+fn serialize_values<F: ParamFormat>(state: &StateMachine, registry: &TruthRegistry) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    F::to_bytes(&state.serializable(registry))
+}
+
+impl<K> Default for Journal<'_,K> {
+    fn default() -> Self {
+        Self { entries: Vec::new(), values: None }
+    }
+}
+
+impl<K: PartialEq> PartialEq for Journal<'_,K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl<'r,K: Hash + Eq + Clone> Journal<'r,K> {
+    /// Creates a new empty journal.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new empty journal, that records the values of the truths in the state after every transition.
+    ///
+    /// The truths are serialized through the given registry in the format `F`, so every truth in the state has to be registered.
+    /// `replay` compares the recorded values with the values of the truths in the replayed state.
+    pub fn with_values<F: ParamFormat>(registry: &'r TruthRegistry) -> Self {
+        Self { entries: Vec::new(), values: Some(Values { registry, serialize: serialize_values::<F> }) }
+    }
+
+    /// Sets the registry and format the values of the truths are serialized with, for example after this journal was deserialized.
+    ///
+    /// The values are serialized like in `with_values`.
+    pub fn set_values<F: ParamFormat>(&mut self, registry: &'r TruthRegistry) {
+        self.values = Some(Values { registry, serialize: serialize_values::<F> });
+    }
+
+    /// The transitions recorded in this journal, in the order they were run.
+    pub fn entries(&self) -> &[JournalEntry<K>] {
+        &self.entries
+    }
+
+    /// Runs the transition at the given key path of the dictionary, and records it in this journal.
+    ///
+    /// The transition is recorded whether it succeeds or fails, and its error is returned.
+    /// If no transition is stored at the key path, nothing is recorded. If the values of the truths can not be
+    /// serialized, the transition is recorded without them, and `JournalError::Values` is returned.
+    pub fn run(&mut self, dictionary: &mut TransitionDictionary<'_,K>, state: &mut StateMachine, path: &[K]) -> Result<(), JournalError> {
+        let result = run_transition(dictionary, state, path);
+        self.record(state, path, None, result)
+    }
+
+    /// Runs the transition at the given key path of the dictionary with the given parameters, and records it in this journal.
+    ///
+    /// The parameters are serialized and deserialized into the parameters of the transition, so they are
+    /// recorded exactly as the transition received them. If no transition is stored at the key path,
+    /// or the parameters can not be converted, nothing is recorded.
+    pub fn run_with<'a,F,Param>(&mut self, dictionary: &ParameterizedDictionary<'a,K,F>, state: &mut StateMachine, path: &[K], params: Param) -> Result<(), JournalError>
+    where
+        F: ParamFormat,
+        Param: Serialize
+    {
+        let params = F::to_bytes(&params).map_err(|e| JournalError::Params(ParamsError(e)))?;
+        let result = run_parameterized(dictionary, state, path, &params);
+        self.record(state, path, Some(params), result)
+    }
+
+    /// Replays the transitions recorded in this journal on the given state machine.
+    ///
+    /// The transitions are taken from the same key paths of the given dictionaries, and run with the recorded parameters.
+    /// Returns the first entry that did not succeed or fail like it did when it was recorded, or after which the state
+    /// contains different truths. If both the entry and this journal record values, the values of the truths are
+    /// compared as well. The state machine keeps the changes of all transitions up to the divergence.
+    pub fn replay<'a,F: ParamFormat>(&self, dictionary: &mut TransitionDictionary<'a,K>, parameterized: &ParameterizedDictionary<'a,K,F>, state: &mut StateMachine) -> Result<(), Divergence<K>> {
+        for (index, entry) in self.entries.iter().enumerate() {
+            let diverge = |kind| Divergence { index, path: entry.path.clone(), kind };
+
+            let result = match &entry.params {
+                Some(params) => run_parameterized(parameterized, state, &entry.path, params),
+                None => run_transition(dictionary, state, &entry.path)
+            };
+
+            match (result, entry.succeeded) {
+                (Ok(()), true) | (Err(JournalError::Transition(_)), false) => {},
+                (Ok(()), false) => return Err(diverge(DivergenceKind::Succeeded)),
+                (Err(e), _) => return Err(diverge(DivergenceKind::Failed(e)))
+            }
+
+            let truths = truth_names(state);
+            if truths != entry.truths {
+                return Err(diverge(DivergenceKind::Truths { expected: entry.truths.clone(), actual: truths }));
+            }
+
+            if let (Some(expected), Some(values)) = (&entry.values, &self.values) {
+                let actual = values.of(state).map_err(|e| diverge(DivergenceKind::Failed(e)))?;
+                if &actual != expected {
+                    return Err(diverge(DivergenceKind::Values { expected: expected.clone(), actual }));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, state: &StateMachine, path: &[K], params: Option<Vec<u8>>, result: Result<(), JournalError>) -> Result<(), JournalError> {
+        if !matches!(result, Ok(()) | Err(JournalError::Transition(_))) {
+            return result;
+        }
+
+        let (values, recorded) = match self.values.map(|values| values.of(state)).transpose() {
+            Ok(values) => (values, Ok(())),
+            Err(e) => (None, Err(e))
+        };
+        self.entries.push(JournalEntry {
+            path: path.to_vec(),
+            params,
+            succeeded: result.is_ok(),
+            truths: truth_names(state),
+            values
+        });
+        result.and(recorded)
+    }
+}
+
+fn run_transition<K: Hash + Eq + Clone>(dictionary: &mut TransitionDictionary<'_,K>, state: &mut StateMachine, path: &[K]) -> Result<(), JournalError> {
+    let transition = dictionary.get_deep_mut(path).ok_or(JournalError::UnknownTransition)?;
+    state.run_ref_mut(transition).map_err(JournalError::Transition)
+}
+
+fn run_parameterized<K: Hash + Eq + Clone, F: ParamFormat>(dictionary: &ParameterizedDictionary<'_,K,F>, state: &mut StateMachine, path: &[K], params: &[u8]) -> Result<(), JournalError> {
+    let transition = dictionary.get_deep(path).ok_or(JournalError::UnknownTransition)?.build(params)?;
+    state.run(transition).map_err(JournalError::Transition)
+}
+
+/// The sorted names of the truths in the state, which do not change between builds like their ids do.
+fn truth_names(state: &StateMachine) -> Vec<String> {
+    let mut names: Vec<String> = state.truths().map(|id| id.name().to_string()).collect();
+    names.sort();
+    names
+}
@@ -1,9 +1,19 @@
+mod analysis;
 mod dict;
+mod export;
+#[cfg(feature = "serde")]
+mod journal;
+mod plan;
+mod schedule;
 
-use std::hash::Hash;
+use std::{collections::HashSet, hash::Hash};
 use pssm_core::{transition::{IntoTransitionMut, TransitionMut, InvalidTransitionError}, StateMachine};
 
+pub use analysis::{Analysis, StateSpace};
 pub use dict::Dictionary;
+#[cfg(feature = "serde")]
+pub use journal::{Divergence, DivergenceKind, Journal, JournalEntry, JournalError, ParamFormat, ParameterizedDictionary, ParameterizedTransition, ParamsError};
+pub use schedule::{Outcome, Policy, Scheduler, Trace};
 
 /// A dictionary of transitions.
 /// 
@@ -43,6 +53,8 @@ impl<'a,K: Hash + Eq + Clone> TransitionDictionary<'a,K> {
     /// that can be run in the given state. This function will also recursively check all folders
     /// in this dictionary for transitions that can be run in the given state.
     /// 
+    /// Guards attached to the transitions are evaluated as well, without consuming any truths.
+    /// 
     /// # Examples
     /// 
     /// ```
@@ -92,6 +104,68 @@ impl<'a,K: Hash + Eq + Clone> TransitionDictionary<'a,K> {
         runnables
     }
 
+    /// Returns the key paths of a maximal set of runnable transitions that do not conflict with each other.
+    /// 
+    /// Two transitions conflict if they require some of the same truths, counting every truth of their alternatives.
+    /// The runnable transitions in this dictionary and all of its folders are ordered by their priority, highest first, and then by their key paths.
+    /// Every transition is selected in that order, unless it conflicts with a transition that was already selected.
+    /// The result only depends on the state and the transitions, not on the order they are stored in.
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// use pssm::prelude::*;
+    /// 
+    /// #[derive(Debug,Truth)]
+    /// struct Ticket();
+    /// 
+    /// #[derive(Debug,Truth)]
+    /// struct Log();
+    /// 
+    /// fn approve(_: Ticket) {}
+    /// 
+    /// fn reject(_: Ticket) {}
+    /// 
+    /// fn rotate(_: Log) {}
+    /// 
+    /// let mut transitions = TransitionDictionary::new();
+    /// transitions.add_transition("approve", approve).unwrap();
+    /// transitions.add_transition("reject", reject.into_transition_mut().unwrap().with_priority(1)).unwrap();
+    /// transitions.add_transition("rotate", rotate).unwrap();
+    /// 
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(Ticket());
+    /// state_machine.set_truth(Log());
+    /// 
+    /// assert_eq!(transitions.resolve_conflicts(&state_machine), vec![vec!["reject"], vec!["rotate"]]);
+    /// ```
+    pub fn resolve_conflicts(&self, state: &StateMachine) -> Vec<Vec<K>>
+    where 
+        K: Ord
+    {
+        let mut runnables: Vec<_> = self.iter_deep().into_iter()
+            .filter(|(_, transition)| state.can_run_transition_mut(transition))
+            .collect();
+        runnables.sort_by(|(a_path, a), (b_path, b)| b.priority().cmp(&a.priority()).then_with(|| a_path.cmp(b_path)));
+
+        let mut required = HashSet::new();
+        let mut selected = Vec::new();
+
+        for (path, transition) in runnables {
+            let signature = transition.signature();
+            let requires: HashSet<_> = signature.requires().iter()
+                .chain(signature.alternatives().iter().flatten())
+                .copied()
+                .collect();
+            if requires.is_disjoint(&required) {
+                required.extend(requires);
+                selected.push(path);
+            }
+        }
+
+        selected
+    }
+
     /// Adds a transition to this dictionary.
     /// 
     /// This function will add a transition to this dictionary with the given key. If a transition
@@ -0,0 +1,168 @@
+use std::{collections::{BTreeSet, HashMap, VecDeque}, hash::Hash};
+use pssm_core::{transition::Signature, TruthId};
+
+use crate::TransitionDictionary;
+
+/// A set of truths, standing in for every state that contains exactly these truths.
+pub(crate) type Truths = BTreeSet<TruthId>;
+
+/// Checks if a transition with the given signature can run in a state with the given truths.
+pub(crate) fn can_apply(signature: &Signature, truths: &Truths) -> bool {
+    signature.requires().iter().all(|id| truths.contains(id))
+        && signature.alternatives().iter().all(|ids| ids.iter().any(|id| truths.contains(id)))
+        && !signature.forbids().iter().any(|id| truths.contains(id))
+}
+
+/// Returns the truths in the state after a transition with the given signature ran in a state with the given truths.
+fn apply(signature: &Signature, truths: &Truths) -> Truths {
+    let mut consumes = signature.consumes();
+    consumes.extend(signature.alternatives().iter()
+        .filter_map(|ids| ids.iter().find(|id| truths.contains(id)))
+        .filter(|id| !signature.borrows().contains(id)));
+
+    truths.iter()
+        .filter(|id| !consumes.contains(id) && !signature.optional().contains(id))
+        .chain(signature.produces())
+        .copied()
+        .collect()
+}
+
+/// Returns the truths that can be in the state after a transition with the given signature ran in a state with the given truths,
+/// for every combination of the truths it may produce.
+pub(crate) fn outcomes(signature: &Signature, truths: &Truths) -> Vec<Truths> {
+    let mut outcomes = vec![apply(signature, truths)];
+    for id in signature.may_produce() {
+        if outcomes[0].contains(id) {
+            continue;
+        }
+        let with: Vec<Truths> = outcomes.iter()
+            .map(|truths| truths.iter().chain([id]).copied().collect())
+            .collect();
+        outcomes.extend(with);
+    }
+    outcomes
+}
+
+impl<'a,K: Hash + Eq + Clone> TransitionDictionary<'a,K> {
+    /// Plans a sequence of transitions that leads from the given truths to a state containing all of the target truths.
+    ///
+    /// This function will do a breadth-first search over the truths the transitions in this dictionary and all of its
+    /// folders require, forbid, take and produce. It will return the key paths of the transitions in the order they have to run,
+    /// using as few transitions as possible. If no sequence of transitions reaches the target truths, this function will return None.
+    ///
+    /// Only the signatures of the transitions are taken into account. Guards can not be evaluated without a state,
+    /// and a fallible transition is assumed to succeed. A transition that may or may not produce a truth, like one
+    /// returning an `Option`, is followed with every combination of the truths it may produce, so the plan can depend on
+    /// such a transition producing its truth.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Order();
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Paid();
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Shipped();
+    ///
+    /// fn pay(_: &Order) -> Paid {
+    ///     Paid()
+    /// }
+    ///
+    /// fn ship(_: Order, _: Paid) -> Shipped {
+    ///     Shipped()
+    /// }
+    ///
+    /// let mut shipping = TransitionDictionary::new();
+    /// shipping.add_transition("ship", ship).unwrap();
+    ///
+    /// let mut transitions = TransitionDictionary::new();
+    /// transitions.add_transition("pay", pay).unwrap();
+    /// transitions.insert_folder("shipping", shipping);
+    ///
+    /// let mut state_machine = StateMachine::new();
+    /// state_machine.set_truth(Order());
+    ///
+    /// let plan = transitions.plan(state_machine.truths(), [TruthId::of::<Shipped>()]).unwrap();
+    /// assert_eq!(plan, vec![vec!["pay"], vec!["shipping", "ship"]]);
+    ///
+    /// assert!(transitions.plan([], [TruthId::of::<Shipped>()]).is_none());
+    /// ```
+    ///
+    /// A plan can rely on a transition with an `Option` output producing its truth.
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Order(u32);
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Paid();
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Shipped();
+    ///
+    /// fn pay(order: &Order) -> Option<Paid> {
+    ///     (order.0 > 0).then_some(Paid())
+    /// }
+    ///
+    /// fn ship(_: Paid) -> Shipped {
+    ///     Shipped()
+    /// }
+    ///
+    /// let mut transitions = TransitionDictionary::new();
+    /// transitions.add_transition("pay", pay).unwrap();
+    /// transitions.add_transition("ship", ship).unwrap();
+    ///
+    /// let plan = transitions.plan([TruthId::of::<Order>()], [TruthId::of::<Shipped>()]).unwrap();
+    /// assert_eq!(plan, vec![vec!["pay"], vec!["ship"]]);
+    /// ```
+    pub fn plan<S,T>(&self, start: S, target: T) -> Option<Vec<Vec<K>>>
+    where
+        S: IntoIterator<Item = TruthId>,
+        T: IntoIterator<Item = TruthId>
+    {
+        let transitions = self.iter_deep();
+        let target: Truths = target.into_iter().collect();
+        let start: Truths = start.into_iter().collect();
+
+        let mut parents: HashMap<Truths, Option<(Truths, usize)>> = HashMap::new();
+        let mut queue = VecDeque::new();
+        parents.insert(start.clone(), None);
+        queue.push_back(start);
+
+        while let Some(truths) = queue.pop_front() {
+            if target.is_subset(&truths) {
+                let mut plan = Vec::new();
+                let mut current = &truths;
+                while let Some(Some((parent, index))) = parents.get(current) {
+                    plan.push(transitions[*index].0.clone());
+                    current = parent;
+                }
+                plan.reverse();
+                return Some(plan);
+            }
+
+            for (index, (_, transition)) in transitions.iter().enumerate() {
+                let signature = transition.signature();
+                if !can_apply(signature, &truths) {
+                    continue;
+                }
+
+                for next in outcomes(signature, &truths) {
+                    if !parents.contains_key(&next) {
+                        parents.insert(next.clone(), Some((truths.clone(), index)));
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
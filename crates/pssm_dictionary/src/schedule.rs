@@ -0,0 +1,245 @@
+use std::{fmt::Debug, hash::Hash};
+use pssm_core::{StateMachine, TransitionCallError};
+
+use crate::TransitionDictionary;
+
+type PriorityFn<K> = dyn Fn(&[K]) -> i64;
+
+/// How a `Scheduler` selects the next transition to run from the runnable transitions.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+/// use pssm::dictionary::{Policy, Scheduler};
+///
+/// #[derive(Debug,Truth)]
+/// struct Ticket();
+///
+/// fn close(_: Ticket) {}
+///
+/// fn escalate(_: Ticket) {}
+///
+/// let mut transitions = TransitionDictionary::new();
+/// transitions.add_transition("close", close).unwrap();
+/// transitions.add_transition("escalate", escalate.into_transition_mut().unwrap().with_priority(1)).unwrap();
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Ticket());
+/// let trace = Scheduler::new(Policy::TransitionPriority, 10).run(&mut transitions, &mut state_machine);
+/// assert_eq!(trace.steps(), &[vec!["escalate"]]);
+///
+/// state_machine.set_truth(Ticket());
+/// let policy = Policy::Priority(Box::new(|path: &[&str]| if path[0] == "close" { 1 } else { 0 }));
+/// let trace = Scheduler::new(policy, 10).run(&mut transitions, &mut state_machine);
+/// assert_eq!(trace.steps(), &[vec!["close"]]);
+/// ```
+pub enum Policy<K> {
+    /// Runs the runnable transition with the smallest key path.
+    FirstByKey,
+    /// Runs the runnable transition with the highest priority, given by a function of its key path.
+    /// Transitions with the same priority are run in the order of their key paths.
+    Priority(Box<PriorityFn<K>>),
+    /// Runs the runnable transition with the highest priority of its own, see `TransitionMut::with_priority`.
+    /// Transitions with the same priority are run in the order of their key paths.
+    TransitionPriority,
+    /// Runs the runnable transitions in turns, in the order of their key paths.
+    RoundRobin,
+    /// Runs a random runnable transition. The same seed selects the same transitions.
+    Random(u64)
+}
+
+impl<K> Debug for Policy<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Policy::FirstByKey => f.write_str("FirstByKey"),
+            Policy::Priority(_) => f.write_str("Priority"),
+            Policy::TransitionPriority => f.write_str("TransitionPriority"),
+            Policy::RoundRobin => f.write_str("RoundRobin"),
+            Policy::Random(seed) => write!(f, "Random({})", seed)
+        }
+    }
+}
+
+/// Why a `Scheduler` stopped running transitions.
+#[derive(Debug)]
+pub enum Outcome<K> {
+    /// No transition was runnable anymore.
+    Fixpoint,
+    /// The step limit was reached while transitions were still runnable.
+    StepLimit,
+    /// The transition with the given key path failed. Its changes were rolled back.
+    Failed(Vec<K>, TransitionCallError)
+}
+
+/// The transitions a `Scheduler` ran, and why it stopped.
+#[derive(Debug)]
+pub struct Trace<K> {
+    steps: Vec<Vec<K>>,
+    outcome: Outcome<K>
+}
+
+impl<K> Trace<K> {
+    /// The key paths of the transitions that were run, in the order they were run.
+    pub fn steps(&self) -> &[Vec<K>] {
+        &self.steps
+    }
+
+    /// Why the scheduler stopped.
+    pub fn outcome(&self) -> &Outcome<K> {
+        &self.outcome
+    }
+
+    /// Checks if the scheduler stopped because no transition was runnable anymore.
+    pub fn reached_fixpoint(&self) -> bool {
+        matches!(self.outcome, Outcome::Fixpoint)
+    }
+}
+
+/// A small deterministic random number generator (SplitMix64), so that seeded runs can be reproduced.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Runs the transitions of a `TransitionDictionary` until none of them is runnable anymore.
+///
+/// In every step, the scheduler collects the runnable transitions of the dictionary and all of its folders,
+/// selects one of them with its `Policy` and runs it. It stops when no transition is runnable anymore,
+/// when the step limit is reached, or when a transition fails.
+///
+/// # Examples
+///
+/// ```
+/// use pssm::prelude::*;
+/// use pssm::dictionary::{Policy, Scheduler};
+///
+/// #[derive(Debug,Truth)]
+/// struct Order();
+///
+/// #[derive(Debug,Truth)]
+/// struct Paid();
+///
+/// #[derive(Debug,Truth)]
+/// struct Shipped();
+///
+/// fn pay(_: &Order, _: Absent<Paid>) -> Paid {
+///     Paid()
+/// }
+///
+/// fn ship(_: Order, _: Paid) -> Shipped {
+///     Shipped()
+/// }
+///
+/// let mut transitions = TransitionDictionary::new();
+/// transitions.add_transition("pay", pay).unwrap();
+/// transitions.add_transition("ship", ship).unwrap();
+///
+/// let mut state_machine = StateMachine::new();
+/// state_machine.set_truth(Order());
+///
+/// let trace = Scheduler::new(Policy::FirstByKey, 10).run(&mut transitions, &mut state_machine);
+///
+/// assert!(trace.reached_fixpoint());
+/// assert_eq!(trace.steps(), &[vec!["pay"], vec!["ship"]]);
+/// assert!(state_machine.has_truth::<Shipped>());
+/// ```
+#[derive(Debug)]
+pub struct Scheduler<K> {
+    policy: Policy<K>,
+    step_limit: usize
+}
+
+impl<K: Hash + Eq + Clone + Ord> Scheduler<K> {
+    /// Creates a new scheduler with the given policy, that runs at most `step_limit` transitions.
+    pub fn new(policy: Policy<K>, step_limit: usize) -> Self {
+        Self { policy, step_limit }
+    }
+
+    /// Runs the transitions of the given dictionary on the given state machine, until none of them is runnable anymore.
+    ///
+    /// Returns the key paths of the transitions that were run, and why the scheduler stopped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pssm::prelude::*;
+    /// use pssm::dictionary::{Outcome, Policy, Scheduler};
+    ///
+    /// #[derive(Debug,Truth)]
+    /// struct Light();
+    ///
+    /// fn switch_on(_: Absent<Light>) -> Light {
+    ///     Light()
+    /// }
+    ///
+    /// fn switch_off(_: Light) {}
+    ///
+    /// let mut transitions = TransitionDictionary::new();
+    /// transitions.add_transition("on", switch_on).unwrap();
+    /// transitions.add_transition("off", switch_off).unwrap();
+    ///
+    /// let mut state_machine = StateMachine::new();
+    /// let trace = Scheduler::new(Policy::RoundRobin, 3).run(&mut transitions, &mut state_machine);
+    ///
+    /// assert!(matches!(trace.outcome(), Outcome::StepLimit));
+    /// assert_eq!(trace.steps(), &[vec!["on"], vec!["off"], vec!["on"]]);
+    /// ```
+    pub fn run(&self, dictionary: &mut TransitionDictionary<'_,K>, state: &mut StateMachine) -> Trace<K> {
+        let mut paths: Vec<Vec<K>> = dictionary.iter_deep().into_iter().map(|(path, _)| path).collect();
+        paths.sort();
+
+        let mut steps = Vec::new();
+        let mut last = None;
+        let mut rng = match self.policy {
+            Policy::Random(seed) => SplitMix64(seed),
+            _ => SplitMix64(0)
+        };
+
+        loop {
+            let runnable: Vec<usize> = (0..paths.len())
+                .filter(|index| dictionary.get_deep(&paths[*index])
+                    .is_some_and(|transition| state.can_run_transition_mut(transition)))
+                .collect();
+
+            if runnable.is_empty() {
+                return Trace { steps, outcome: Outcome::Fixpoint };
+            }
+            if steps.len() == self.step_limit {
+                return Trace { steps, outcome: Outcome::StepLimit };
+            }
+
+            let index = match &self.policy {
+                Policy::FirstByKey => runnable[0],
+                Policy::Priority(priority) => *runnable.iter()
+                    .rev()
+                    .max_by_key(|index| priority(&paths[**index]))
+                    .expect("runnable transitions are not empty"),
+                Policy::TransitionPriority => *runnable.iter()
+                    .rev()
+                    .max_by_key(|index| dictionary.get_deep(&paths[**index]).map_or(i32::MIN, |transition| transition.priority()))
+                    .expect("runnable transitions are not empty"),
+                Policy::RoundRobin => *runnable.iter()
+                    .find(|index| last.is_none_or(|last| **index > last))
+                    .unwrap_or(&runnable[0]),
+                Policy::Random(_) => runnable[(rng.next() % runnable.len() as u64) as usize]
+            };
+
+            let path = &paths[index];
+            let transition = dictionary.get_deep_mut(path).expect("transition was removed from the dictionary");
+            if let Err(e) = state.run_ref_mut(transition) {
+                return Trace { steps, outcome: Outcome::Failed(path.clone(), e) };
+            }
+
+            steps.push(path.clone());
+            last = Some(index);
+        }
+    }
+}
@@ -1,19 +1,82 @@
-
 use proc_macro::TokenStream;
 use syn::parse_macro_input;
 use quote::quote;
 
-#[proc_macro_derive(Truth)]
+/// The options set with `#[truth(...)]` attributes.
+#[derive(Default)]
+struct TruthOptions {
+    clone: bool,
+    tags: Vec<syn::LitStr>
+}
+
+impl TruthOptions {
+    fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut options = Self::default();
+
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("truth")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("clone") {
+                    options.clone = true;
+                    Ok(())
+                } else if meta.path.is_ident("tag") {
+                    options.tags.push(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported truth option"))
+                }
+            })?;
+        }
+
+        Ok(options)
+    }
+}
+
+/// Derives `Truth` for a type.
+///
+/// Supported options, set with `#[truth(...)]`:
+///
+/// - `clone`: the truth is cloned with `Clone` when a snapshot of the state is taken.
+/// - `tag = "..."`: the truth is marked with the tag, so that it is taken by `Tagged` inputs. Can be set multiple times.
+#[proc_macro_derive(Truth, attributes(truth))]
 pub fn truth_derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as syn::DeriveInput);
     let name = &input.ident;
+    let options = match TruthOptions::parse(&input.attrs) {
+        Ok(options) => options,
+        Err(e) => return e.to_compile_error().into()
+    };
+
+    let try_clone = options.clone.then(|| quote! {
+        #[inline]
+        fn try_clone(&self) -> Option<Self> {
+            Some(std::clone::Clone::clone(self))
+        }
+    });
+
+    let tags = &options.tags;
+    let tags = (!tags.is_empty()).then(|| quote! {
+        #[inline]
+        fn tags() -> &'static [&'static str] {
+            &[#(#tags),*]
+        }
+    });
+
     let gen = quote! {
         impl Truth for #name {
             #[inline]
             fn id() -> std::any::TypeId {
                 std::any::TypeId::of::<#name>()
             }
+
+            #[inline]
+            fn name() -> &'static str {
+                std::any::type_name::<#name>()
+            }
+
+            #try_clone
+
+            #tags
         }
     };
     gen.into()
-}
\ No newline at end of file
+}